@@ -12,3 +12,23 @@ pub const PRESSED_BUTTON_DARK: Color = Color::rgb(0.46, 0.46, 0.46);
 pub const HOVERED_BUTTON_DARK: Color = Color::rgb(0.58, 0.58, 0.58);
 
 pub const TRANSPARENT_COLOR: Color = Color::rgba(0., 0., 0., 0.);
+
+/// Colors the lit square cycles through in the color modality.
+pub const STIMULUS_COLORS: [Color; 8] = [
+    Color::rgb(0.839, 0.188, 0.192),
+    Color::rgb(0.941, 0.533, 0.110),
+    Color::rgb(0.851, 0.749, 0.094),
+    Color::rgb(0.192, 0.639, 0.290),
+    Color::rgb(0.098, 0.651, 0.749),
+    Color::rgb(0.176, 0.361, 0.851),
+    Color::rgb(0.561, 0.306, 0.800),
+    Color::rgb(0.851, 0.306, 0.651),
+];
+
+/// Colors of the position streams in multi-stimulus mode, first stream first.
+pub const POSITION_STREAM_COLORS: [Color; 4] = [
+    STIMULUS_COLORS[5],
+    STIMULUS_COLORS[0],
+    STIMULUS_COLORS[3],
+    STIMULUS_COLORS[1],
+];
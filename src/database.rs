@@ -1,10 +1,13 @@
-use crate::AppState;
+use crate::{
+    AppState, ArithmeticProblem, DetectionStats, EngineConfig, Lures, ModalityKind, NBackVariant,
+    PolicyState, Response, Stimulus, Targets, DEFAULT_SOUND_PACK,
+};
 use bevy::prelude::*;
-use bevy_inspector_egui::prelude::*;
 use bevy_pkv::PkvStore;
-use chrono::{Datelike, Local, NaiveDate};
+use chrono::{DateTime, Datelike, Local, NaiveDate};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use strum_macros::EnumIter;
 
 pub struct DatabasePlugin;
 
@@ -24,16 +27,156 @@ pub enum Mode {
     Manual,
 }
 
+#[derive(EnumIter, Default, Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum GameMode {
+    #[default]
+    Dual,
+    Variable,
+    Crab,
+    Triple,
+    Quad,
+    Arithmetic,
+    /// Several squares per trial, each its own position stream.
+    MultiStimulus,
+    /// Visual and spoken letters, matched within and across the two.
+    Combination,
+    /// Any set of modalities, picked in the settings.
+    Custom,
+}
+
+impl GameMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            GameMode::Dual => "Dual N-Back",
+            GameMode::Variable => "Variable N-Back",
+            GameMode::Crab => "Crab-Back",
+            GameMode::Triple => "Triple N-Back",
+            GameMode::Quad => "Quad N-Back",
+            GameMode::Arithmetic => "Arithmetic N-Back",
+            GameMode::MultiStimulus => "Multi-Stimulus",
+            GameMode::Combination => "Combination N-Back",
+            GameMode::Custom => "Custom N-Back",
+        }
+    }
+
+    /// Short name used on the scoreboard, e.g. "DNB".
+    pub fn abbreviation(&self) -> &'static str {
+        match self {
+            GameMode::Dual => "DNB",
+            GameMode::Variable => "VNB",
+            GameMode::Crab => "CNB",
+            GameMode::Triple => "TNB",
+            GameMode::Quad => "QNB",
+            GameMode::Arithmetic => "ANB",
+            GameMode::MultiStimulus => "MSNB",
+            GameMode::Combination => "CBNB",
+            GameMode::Custom => "Custom",
+        }
+    }
+
+    pub fn variant(&self) -> NBackVariant {
+        match self {
+            GameMode::Dual
+            | GameMode::Triple
+            | GameMode::Quad
+            | GameMode::Arithmetic
+            | GameMode::MultiStimulus
+            | GameMode::Combination
+            | GameMode::Custom => NBackVariant::Standard,
+            GameMode::Variable => NBackVariant::Variable,
+            GameMode::Crab => NBackVariant::Crab,
+        }
+    }
+
+    /// The modalities the user responds to in this mode. Multi-stimulus and custom mode depend
+    /// on the settings and have none of their own; see [`SettingValues::modalities`].
+    pub fn modalities(&self) -> Vec<ModalityKind> {
+        match self {
+            GameMode::Dual | GameMode::Variable | GameMode::Crab => {
+                vec![ModalityKind::POSITION, ModalityKind::AUDIO]
+            }
+            GameMode::Triple => vec![
+                ModalityKind::POSITION,
+                ModalityKind::AUDIO,
+                ModalityKind::COLOR,
+            ],
+            GameMode::Quad => vec![
+                ModalityKind::POSITION,
+                ModalityKind::AUDIO,
+                ModalityKind::COLOR,
+                ModalityKind::SHAPE,
+            ],
+            GameMode::Arithmetic => vec![ModalityKind::POSITION, ModalityKind::ARITHMETIC],
+            GameMode::Combination => vec![ModalityKind::VISUAL_LETTER, ModalityKind::AUDIO],
+            GameMode::MultiStimulus | GameMode::Custom => Vec::new(),
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum GenerationMode {
+    #[default]
+    Probabilistic,
+    ExactCount,
+}
+
+/// How the positions are arranged on screen.
+#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum LayoutKind {
+    #[default]
+    Grid,
+    Ring,
+    Hexagon,
+    /// Positions strewn over the board, the same every session.
+    Scatter,
+    /// Positions read from [`SettingValues::custom_layout`].
+    Custom,
+}
+
+/// What the tone modality plays.
+#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ToneKind {
+    #[default]
+    Pitch,
+    Chord,
+}
+
+#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum LevelMetric {
+    #[default]
+    PercentCorrect,
+    /// The lowest d′ of the session's modalities that have one, so the weakest one sets the pace.
+    DPrime,
+}
+
+#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum LevelPolicyKind {
+    #[default]
+    Threshold,
+    Jaeggi,
+    BrainWorkshop,
+    Staircase,
+}
+
 #[derive(Resource, Debug, Serialize, Deserialize)]
 pub struct CurrentDate {
     pub date: NaiveDate,
 }
 
 #[derive(Resource, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Session {
+    /// Key suffix of this session's [`SessionLog`] in the store.
+    pub id: u32,
     pub date: NaiveDate,
+    pub mode: GameMode,
+    /// Level the session was played at.
     pub level: u32,
     pub percent_score: u32,
+    pub seed: u64,
+    /// Mean reaction time of correctly spotted matches per modality, in milliseconds.
+    pub reaction_ms: BTreeMap<ModalityKind, u32>,
+    pub detection: BTreeMap<ModalityKind, DetectionStats>,
 }
 
 #[derive(Resource, Clone, Default, Serialize, Deserialize)]
@@ -41,15 +184,226 @@ pub struct RecentSessions {
     pub sessions: VecDeque<Session>,
 }
 
-#[derive(Debug, Resource, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrialRecord {
+    pub index: u32,
+    /// How many trials back this trial was compared against.
+    pub n: u32,
+    pub stimulus: Stimulus,
+    pub targets: Targets,
+    pub lures: Lures,
+    pub response: Response,
+    pub presented_at: DateTime<Local>,
+    pub responded_at: BTreeMap<ModalityKind, DateTime<Local>>,
+    /// Milliseconds from stimulus onset to each modality's response.
+    pub reaction_ms: BTreeMap<ModalityKind, u32>,
+    pub problem: Option<ArithmeticProblem>,
+    /// The result typed in on an arithmetic trial.
+    pub answer: Option<i32>,
+}
+
+/// Every trial of one session, stored under `sessionLog{id}`.
+#[derive(Debug, Clone, Resource, Serialize, Deserialize)]
+pub struct SessionLog {
+    pub started_at: DateTime<Local>,
+    pub mode: GameMode,
+    pub level: u32,
+    pub seed: u64,
+    /// Sound pack the audio stimuli index into.
+    #[serde(default)]
+    pub sound_pack: String,
+    /// What the stimulus sequence was generated from, so the session can be replayed. Logs of
+    /// older versions have only the seed.
+    #[serde(default)]
+    pub config: Option<EngineConfig>,
+    /// The settings the session was played with, which a replay is played with too.
+    #[serde(default)]
+    pub settings: Option<SettingValues>,
+    pub trials: Vec<TrialRecord>,
+}
+
+impl SessionLog {
+    pub fn key(id: u32) -> String {
+        format!("sessionLog{}", id)
+    }
+
+    /// Mean reaction time of correctly spotted `modality` matches, in milliseconds.
+    pub fn mean_reaction_ms(&self, modality: ModalityKind) -> Option<u32> {
+        mean(
+            self.trials
+                .iter()
+                .filter(|trial| trial.targets.contains(&modality))
+                .filter_map(|trial| trial.reaction_ms.get(&modality).copied()),
+        )
+    }
+}
+
+fn mean(values: impl Iterator<Item = u32>) -> Option<u32> {
+    let (sum, count) = values.fold((0, 0), |(sum, count), value| (sum + value, count + 1));
+    (count > 0).then(|| sum / count)
+}
+
+#[derive(Debug, Clone, Resource, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SettingValues {
     pub base_trials: u32,
     pub trial_factor: u32,
     pub trial_exponent: u32,
     pub mode: Mode,
+    pub game_mode: GameMode,
+    /// Modalities played in custom mode.
+    pub custom_modalities: BTreeSet<ModalityKind>,
+    /// Squares lit per trial in multi-stimulus mode, from 2 to 4.
+    pub stimulus_count: u32,
+    pub layout: LayoutKind,
+    /// Size of the position grid.
+    pub grid_columns: u32,
+    pub grid_rows: u32,
+    /// Path of the layout file used by the custom layout.
+    pub custom_layout: String,
+    /// Folder of the sound pack under `assets/sounds`.
+    pub sound_pack: String,
+    pub tone_kind: ToneKind,
+    /// Number of distinct tones, climbing the major scale.
+    pub tone_count: u32,
+    /// Pitch of the lowest tone, in hertz.
+    pub tone_base_frequency: f32,
+    /// Number of stereo positions of spatial audio, spread from far left to far right.
+    pub pan_positions: u32,
+    /// Seconds between the start of consecutive trials at level 1.
+    pub trial_interval: f32,
+    /// Seconds the lit square stays on screen.
+    pub display_duration: f32,
+    /// Seconds taken off the trial interval for each level above 1.
+    pub speed_ramp: f32,
+    pub level_policy: LevelPolicyKind,
+    /// Fraction of correct responses (0.0 to 1.0) above which the threshold policy raises the
+    /// level.
     pub raise_threshold: f32,
+    /// Fraction of correct responses (0.0 to 1.0) below which the threshold policy lowers the
+    /// level.
     pub lower_threshold: f32,
+    pub level_metric: LevelMetric,
+    pub raise_d_prime: f32,
+    pub lower_d_prime: f32,
+    /// Fraction of correct responses (0.0 to 1.0) at or above which the staircase steps up.
+    pub staircase_criterion: f32,
+    pub staircase_up_step: f32,
+    pub staircase_down_step: f32,
     pub chance_of_guaranteed_match: f32,
+    pub generation_mode: GenerationMode,
+    /// Targets of each modality planned by exact count generation. Modalities left out get
+    /// [`Modality::default_targets`](crate::Modality::default_targets).
+    pub modality_targets: BTreeMap<ModalityKind, u32>,
+    /// Targets in every active modality at once.
+    pub dual_targets: u32,
+    /// Lure rate of each modality. Modalities left out get no lures.
+    pub lure_rates: BTreeMap<ModalityKind, f32>,
+    /// Session whose stimulus sequence the next session replays, with the level and settings it
+    /// was played at. Only the next session replays it.
+    pub replay_session: Option<u32>,
+}
+
+impl SettingValues {
+    /// The thresholds used to be saved as percentages. A fraction is never above 1, so any value
+    /// that is must be one of those and is converted.
+    pub fn migrate(&mut self) {
+        for threshold in [
+            &mut self.raise_threshold,
+            &mut self.lower_threshold,
+            &mut self.staircase_criterion,
+        ] {
+            if *threshold > 1.0 {
+                *threshold /= 100.0;
+            }
+        }
+    }
+
+    /// Older versions kept targets and lure rates in a field per group of modalities, with the
+    /// tone and spatial streams going by the audio ones. Modalities without a value of their own
+    /// yet take the one they used to go by.
+    pub fn migrate_modality_settings(&mut self, legacy: &LegacyModalitySettings) {
+        let targets = [
+            (legacy.position_targets, &[ModalityKind::POSITION][..]),
+            (
+                legacy.audio_targets,
+                &[
+                    ModalityKind::AUDIO,
+                    ModalityKind::TONE,
+                    ModalityKind::SPATIAL,
+                ],
+            ),
+            (legacy.color_targets, &[ModalityKind::COLOR]),
+            (legacy.shape_targets, &[ModalityKind::SHAPE]),
+            (legacy.visual_targets, &[ModalityKind::VISUAL_LETTER]),
+            (
+                legacy.cross_modal_targets,
+                &[ModalityKind::VISUAL_AUDIO, ModalityKind::AUDIO_VISUAL],
+            ),
+        ];
+        for (value, modalities) in targets {
+            let Some(value) = value else {
+                continue;
+            };
+            for modality in modalities {
+                self.modality_targets.entry(*modality).or_insert(value);
+            }
+        }
+
+        let lure_rates = [
+            (legacy.position_lure_rate, &[ModalityKind::POSITION][..]),
+            (
+                legacy.audio_lure_rate,
+                &[
+                    ModalityKind::AUDIO,
+                    ModalityKind::TONE,
+                    ModalityKind::SPATIAL,
+                ],
+            ),
+        ];
+        for (value, modalities) in lure_rates {
+            let Some(value) = value else {
+                continue;
+            };
+            for modality in modalities {
+                self.lure_rates.entry(*modality).or_insert(value);
+            }
+        }
+    }
+
+    /// The modalities of the selected game mode.
+    pub fn modalities(&self) -> Vec<ModalityKind> {
+        match self.game_mode {
+            GameMode::MultiStimulus => ModalityKind::POSITIONS
+                .into_iter()
+                .take(self.stimulus_count as usize)
+                .chain([ModalityKind::AUDIO])
+                .collect(),
+            GameMode::Custom => self.custom_modalities.iter().copied().collect(),
+            mode => mode.modalities(),
+        }
+    }
+
+    /// The trial interval after the per-level speed ramp, never shorter than the display time.
+    pub fn trial_interval_at(&self, level: u32) -> f32 {
+        (self.trial_interval - self.speed_ramp * level.saturating_sub(1) as f32)
+            .max(self.display_duration)
+    }
+}
+
+/// The per-modality fields of settings saved by older versions, read to carry them over into
+/// [`SettingValues::modality_targets`] and [`SettingValues::lure_rates`].
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct LegacyModalitySettings {
+    pub position_targets: Option<u32>,
+    pub audio_targets: Option<u32>,
+    pub color_targets: Option<u32>,
+    pub shape_targets: Option<u32>,
+    pub visual_targets: Option<u32>,
+    pub cross_modal_targets: Option<u32>,
+    pub position_lure_rate: Option<f32>,
+    pub audio_lure_rate: Option<f32>,
 }
 
 impl Default for SettingValues {
@@ -59,20 +413,75 @@ impl Default for SettingValues {
             trial_factor: 1,
             trial_exponent: 2,
             mode: Mode::Auto,
-            raise_threshold: 80.0,
-            lower_threshold: 50.0,
+            game_mode: GameMode::Dual,
+            custom_modalities: BTreeSet::from([ModalityKind::POSITION, ModalityKind::AUDIO]),
+            stimulus_count: 2,
+            layout: LayoutKind::Grid,
+            grid_columns: 3,
+            grid_rows: 3,
+            custom_layout: "assets/layouts/custom.txt".to_string(),
+            sound_pack: DEFAULT_SOUND_PACK.to_string(),
+            tone_kind: ToneKind::Pitch,
+            tone_count: 6,
+            tone_base_frequency: 261.63,
+            pan_positions: 5,
+            trial_interval: 3.0,
+            display_duration: 0.5,
+            speed_ramp: 0.0,
+            level_policy: LevelPolicyKind::Threshold,
+            raise_threshold: 0.8,
+            lower_threshold: 0.2,
+            level_metric: LevelMetric::PercentCorrect,
+            raise_d_prime: 2.5,
+            lower_d_prime: 1.0,
+            staircase_criterion: 0.8,
+            staircase_up_step: 0.25,
+            staircase_down_step: 0.75,
             chance_of_guaranteed_match: 12.5,
+            generation_mode: GenerationMode::Probabilistic,
+            modality_targets: BTreeMap::new(),
+            dual_targets: 2,
+            lure_rates: BTreeMap::new(),
+            replay_session: None,
         }
     }
 }
 
 #[derive(Debug, Clone, Resource, Serialize, Deserialize)]
+#[serde(default)]
 pub struct StatValues {
+    /// Level of dual n-back. Other game modes keep theirs in `mode_levels`.
     pub current_level: u32,
-    pub average_level_today: f32,
+    pub mode_levels: HashMap<GameMode, u32>,
+    /// Today's levels of every game mode played today.
+    pub levels_today: HashMap<GameMode, LevelTally>,
+    /// Sessions of any game mode today.
     pub sessions_today: u32,
     pub total_sessions: u32,
     pub last_sync_date: NaiveDate,
+    /// What the level policy remembers between sessions, kept apart for every game mode like
+    /// its level.
+    pub level_states: HashMap<GameMode, PolicyState>,
+}
+
+impl StatValues {
+    pub fn level(&self, mode: GameMode) -> u32 {
+        match mode {
+            GameMode::Dual => self.current_level,
+            _ => self.mode_levels.get(&mode).copied().unwrap_or(1),
+        }
+    }
+
+    pub fn level_mut(&mut self, mode: GameMode) -> &mut u32 {
+        match mode {
+            GameMode::Dual => &mut self.current_level,
+            _ => self.mode_levels.entry(mode).or_insert(1),
+        }
+    }
+
+    pub fn level_state_mut(&mut self, mode: GameMode) -> &mut PolicyState {
+        self.level_states.entry(mode).or_default()
+    }
 }
 
 impl Default for StatValues {
@@ -80,20 +489,44 @@ impl Default for StatValues {
         let now = Local::now();
         Self {
             current_level: 1,
-            average_level_today: 0.0,
+            mode_levels: HashMap::new(),
+            levels_today: HashMap::new(),
             sessions_today: 0,
             total_sessions: 0,
             last_sync_date: NaiveDate::from_ymd_opt(now.year(), now.month(), now.day()).unwrap(),
+            level_states: HashMap::new(),
         }
     }
 }
 
+/// Average and best level over a run of sessions of one game mode.
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize)]
+pub struct LevelTally {
+    pub average_level: f32,
+    pub sessions: u32,
+    pub max_level: u32,
+}
+
+impl LevelTally {
+    pub fn record(&mut self, level: u32) {
+        self.average_level =
+            (self.average_level * self.sessions as f32 + level as f32) / (self.sessions + 1) as f32;
+        self.sessions += 1;
+        self.max_level = self.max_level.max(level);
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DayEntry {
     pub date: NaiveDate,
+    /// Average over the day's sessions of every game mode, whose levels aren't comparable; see
+    /// `modes` for each mode on its own.
     pub average_level: f32,
     pub sessions_completed: u32,
+    /// Highest level reached in any game mode.
     pub max_level: u32,
+    #[serde(default)]
+    pub modes: HashMap<GameMode, LevelTally>,
 }
 
 #[derive(Debug, Default, Clone, Resource, Serialize, Deserialize)]
@@ -101,6 +534,7 @@ pub struct EntryValues {
     pub day_entries: HashMap<NaiveDate, DayEntry>,
 }
 
+#[allow(dead_code)]
 pub fn clear_database(mut pkv: ResMut<PkvStore>) {
     info!("Clearing Database");
     pkv.clear().expect("failed to clear database");
@@ -114,8 +548,14 @@ fn setup_time(mut commands: Commands) {
 }
 
 pub fn setup_database(mut commands: Commands, mut pkv: ResMut<PkvStore>) {
-    if let Ok(settings) = pkv.get::<SettingValues>("settingValues") {
+    if let Ok(mut settings) = pkv.get::<SettingValues>("settingValues") {
         info!("Loaded Prior Settings");
+        settings.migrate();
+        if let Ok(legacy) = pkv.get::<LegacyModalitySettings>("settingValues") {
+            settings.migrate_modality_settings(&legacy);
+        }
+        pkv.set("settingValues", &settings)
+            .expect("failed to store settings");
         commands.insert_resource(settings);
     } else {
         info!("Initialized Default Settings");
@@ -172,7 +612,7 @@ fn sync_stats(mut pkv: ResMut<PkvStore>, stats: ResMut<StatValues>) {
     if stats.last_sync_date != date_today {
         let mut new_stats = stats.clone();
         new_stats.sessions_today = 0;
-        new_stats.average_level_today = 0.0;
+        new_stats.levels_today.clear();
         new_stats.last_sync_date = date_today;
 
         pkv.set("statValues", &new_stats)
@@ -182,3 +622,57 @@ fn sync_stats(mut pkv: ResMut<PkvStore>, stats: ResMut<StatValues>) {
             .expect("failed to store stats");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_turns_percent_thresholds_into_fractions() {
+        let mut settings = SettingValues {
+            raise_threshold: 80.0,
+            lower_threshold: 50.0,
+            staircase_criterion: 75.0,
+            ..Default::default()
+        };
+        settings.migrate();
+        assert_eq!(settings.raise_threshold, 0.8);
+        assert_eq!(settings.lower_threshold, 0.5);
+        assert_eq!(settings.staircase_criterion, 0.75);
+
+        // Fractions are left as they are.
+        let mut settings = SettingValues::default();
+        settings.migrate();
+        assert_eq!(settings.raise_threshold, 0.8);
+        assert_eq!(settings.lower_threshold, 0.2);
+        assert_eq!(settings.staircase_criterion, 0.8);
+    }
+
+    #[test]
+    fn migrate_modality_settings_spreads_shared_fields_over_their_modalities() {
+        let mut settings = SettingValues {
+            modality_targets: BTreeMap::from([(ModalityKind::TONE, 6)]),
+            ..Default::default()
+        };
+        settings.migrate_modality_settings(&LegacyModalitySettings {
+            audio_targets: Some(3),
+            cross_modal_targets: Some(1),
+            position_lure_rate: Some(0.25),
+            ..Default::default()
+        });
+
+        assert_eq!(settings.modality_targets[&ModalityKind::AUDIO], 3);
+        assert_eq!(settings.modality_targets[&ModalityKind::SPATIAL], 3);
+        assert_eq!(settings.modality_targets[&ModalityKind::AUDIO_VISUAL], 1);
+        // A value of the modality's own is kept.
+        assert_eq!(settings.modality_targets[&ModalityKind::TONE], 6);
+        // Fields that were not saved add nothing.
+        assert!(!settings
+            .modality_targets
+            .contains_key(&ModalityKind::POSITION));
+        assert_eq!(
+            settings.lure_rates,
+            BTreeMap::from([(ModalityKind::POSITION, 0.25)])
+        );
+    }
+}
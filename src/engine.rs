@@ -0,0 +1,1391 @@
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::{Mutex, PoisonError},
+};
+use strum_macros::EnumIter;
+
+/// A stimulus channel the user keeps track of, or a comparison across two channels, each
+/// responded to on its own button. The built-in ones are constants here, and a plugin names its
+/// own with [`ModalityKind::new`]. Stored as its id, so ids must stay the same across versions.
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub struct ModalityKind(&'static str);
+
+impl ModalityKind {
+    pub const POSITION: ModalityKind = ModalityKind::new("Position");
+    /// Extra position streams of multi-stimulus mode, each lit in its own color.
+    pub const POSITION2: ModalityKind = ModalityKind::new("Position2");
+    pub const POSITION3: ModalityKind = ModalityKind::new("Position3");
+    pub const POSITION4: ModalityKind = ModalityKind::new("Position4");
+    pub const AUDIO: ModalityKind = ModalityKind::new("Audio");
+    pub const COLOR: ModalityKind = ModalityKind::new("Color");
+    pub const SHAPE: ModalityKind = ModalityKind::new("Shape");
+    /// Spoken digits whose result with the digit N back is typed in, instead of matched.
+    pub const ARITHMETIC: ModalityKind = ModalityKind::new("Arithmetic");
+    /// A letter shown in the grid, drawn from the same letters as the audio.
+    pub const VISUAL_LETTER: ModalityKind = ModalityKind::new("VisualLetter");
+    /// The letter shown now is the letter heard N back.
+    pub const VISUAL_AUDIO: ModalityKind = ModalityKind::new("VisualAudio");
+    /// The letter heard now is the letter shown N back.
+    pub const AUDIO_VISUAL: ModalityKind = ModalityKind::new("AudioVisual");
+    /// A synthesized tone or chord, a non-verbal sound stream.
+    pub const TONE: ModalityKind = ModalityKind::new("Tone");
+    /// Where between the speakers the spoken letter comes from, whatever the letter.
+    pub const SPATIAL: ModalityKind = ModalityKind::new("Spatial");
+
+    /// The built-in modalities, in the order they sort in.
+    pub const BUILT_IN: [ModalityKind; 13] = [
+        ModalityKind::POSITION,
+        ModalityKind::POSITION2,
+        ModalityKind::POSITION3,
+        ModalityKind::POSITION4,
+        ModalityKind::AUDIO,
+        ModalityKind::COLOR,
+        ModalityKind::SHAPE,
+        ModalityKind::ARITHMETIC,
+        ModalityKind::VISUAL_LETTER,
+        ModalityKind::VISUAL_AUDIO,
+        ModalityKind::AUDIO_VISUAL,
+        ModalityKind::TONE,
+        ModalityKind::SPATIAL,
+    ];
+
+    /// The position streams sharing the grid, in stream order.
+    pub const POSITIONS: [ModalityKind; 4] = [
+        ModalityKind::POSITION,
+        ModalityKind::POSITION2,
+        ModalityKind::POSITION3,
+        ModalityKind::POSITION4,
+    ];
+
+    pub const fn new(id: &'static str) -> Self {
+        ModalityKind(id)
+    }
+
+    /// The kind stored as `id`. Ids of no built-in modality are kept for the rest of the run, so
+    /// each is only allocated once.
+    pub fn from_id(id: &str) -> Self {
+        if let Some(kind) = ModalityKind::BUILT_IN.iter().find(|kind| kind.0 == id) {
+            return *kind;
+        }
+
+        static OTHERS: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+        let mut others = OTHERS.lock().unwrap_or_else(PoisonError::into_inner);
+        match others.get(id) {
+            Some(id) => ModalityKind(id),
+            None => {
+                let id: &'static str = Box::leak(id.to_string().into_boxed_str());
+                others.insert(id);
+                ModalityKind(id)
+            }
+        }
+    }
+
+    pub fn id(&self) -> &'static str {
+        self.0
+    }
+
+    /// Label of a built-in modality. Others go by their id; their [`Modality`] gives the label
+    /// shown in the game.
+    ///
+    /// [`Modality`]: crate::Modality
+    pub fn label(&self) -> &'static str {
+        match *self {
+            ModalityKind::POSITION2 => "Position 2",
+            ModalityKind::POSITION3 => "Position 3",
+            ModalityKind::POSITION4 => "Position 4",
+            ModalityKind::VISUAL_LETTER => "Visual",
+            ModalityKind::VISUAL_AUDIO => "Visual-Audio",
+            ModalityKind::AUDIO_VISUAL => "Audio-Visual",
+            ModalityKind::SPATIAL => "Spatial Audio",
+            _ => self.0,
+        }
+    }
+
+    /// Short form of the label, for tight spots like the session history.
+    pub fn abbreviation(&self) -> &'static str {
+        match *self {
+            ModalityKind::POSITION => "P",
+            ModalityKind::POSITION2 => "P2",
+            ModalityKind::POSITION3 => "P3",
+            ModalityKind::POSITION4 => "P4",
+            ModalityKind::AUDIO => "A",
+            ModalityKind::COLOR => "C",
+            ModalityKind::SHAPE => "S",
+            ModalityKind::ARITHMETIC => "Ar",
+            ModalityKind::VISUAL_LETTER => "V",
+            ModalityKind::VISUAL_AUDIO => "VA",
+            ModalityKind::AUDIO_VISUAL => "AV",
+            ModalityKind::TONE => "T",
+            ModalityKind::SPATIAL => "SA",
+            _ => self.0,
+        }
+    }
+
+    /// The channel read on the current trial and the channel read on the trial N back when
+    /// checking for a match. Only the cross-modal comparisons read two different channels.
+    pub fn compares(&self) -> (ModalityKind, ModalityKind) {
+        match *self {
+            ModalityKind::VISUAL_AUDIO => (ModalityKind::VISUAL_LETTER, ModalityKind::AUDIO),
+            ModalityKind::AUDIO_VISUAL => (ModalityKind::AUDIO, ModalityKind::VISUAL_LETTER),
+            _ => (*self, *self),
+        }
+    }
+
+    pub fn is_cross_modal(&self) -> bool {
+        let (current, n_back) = self.compares();
+        current != n_back
+    }
+
+    /// Which of the position streams sharing the grid this is, counting from 0.
+    pub fn position_stream(&self) -> Option<usize> {
+        ModalityKind::POSITIONS.iter().position(|kind| kind == self)
+    }
+
+    pub fn is_position(&self) -> bool {
+        self.position_stream().is_some()
+    }
+
+    /// Built-in modalities come first, in their own order, then the others by id.
+    fn sort_key(&self) -> (usize, &'static str) {
+        let built_in = ModalityKind::BUILT_IN.iter().position(|kind| kind == self);
+        (built_in.unwrap_or(ModalityKind::BUILT_IN.len()), self.id())
+    }
+}
+
+impl Ord for ModalityKind {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+impl PartialOrd for ModalityKind {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Debug for ModalityKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl Serialize for ModalityKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.id())
+    }
+}
+
+impl<'de> Deserialize<'de> for ModalityKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        Ok(ModalityKind::from_id(&id))
+    }
+}
+
+#[derive(EnumIter, Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TargetShape {
+    Circle,
+    Square,
+    Triangle,
+    Diamond,
+    Cross,
+    Star,
+    Hexagon,
+    Ring,
+}
+
+#[derive(EnumIter, Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Operation {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Operation {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Operation::Add => "+",
+            Operation::Subtract => "-",
+            Operation::Multiply => "×",
+            Operation::Divide => "÷",
+        }
+    }
+
+    fn random(rng: &mut impl Rng) -> Self {
+        match rng.gen_range(0..4) {
+            0 => Operation::Add,
+            1 => Operation::Subtract,
+            2 => Operation::Multiply,
+            3 => Operation::Divide,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Combines the digit from N trials back with the current one. Division is only generated
+    /// when it comes out even.
+    fn apply(&self, n_back: u32, current: u32) -> i32 {
+        let (n_back, current) = (n_back as i32, current as i32);
+        match self {
+            Operation::Add => n_back + current,
+            Operation::Subtract => n_back - current,
+            Operation::Multiply => n_back * current,
+            Operation::Divide => n_back / current,
+        }
+    }
+}
+
+/// The sum shown on an arithmetic trial and the answer the user is expected to type.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ArithmeticProblem {
+    pub operation: Operation,
+    pub answer: i32,
+}
+
+/// Everything the user responds to when `channels` are presented: each channel itself, plus
+/// every cross-modal comparison whose two channels are both present.
+pub fn response_categories(channels: &[ModalityKind]) -> Vec<ModalityKind> {
+    let mut categories = channels.to_vec();
+    for cross_modal in [ModalityKind::VISUAL_AUDIO, ModalityKind::AUDIO_VISUAL] {
+        let (current, n_back) = cross_modal.compares();
+        if channels.contains(&current) && channels.contains(&n_back) {
+            categories.push(cross_modal);
+        }
+    }
+    categories
+}
+
+/// What is shown and played on a single trial: for every active modality, the index of the
+/// stimulus drawn from that modality's set.
+pub type Stimulus = BTreeMap<ModalityKind, u32>;
+
+/// A near-miss non-target that repeats the stimulus from one trial closer or further back than N.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Lure {
+    NMinusOne,
+    NPlusOne,
+}
+
+/// Which modalities of a trial were generated as lures.
+pub type Lures = BTreeMap<ModalityKind, Lure>;
+
+/// Which modalities of a trial repeat the stimulus from N trials back.
+pub type Targets = BTreeSet<ModalityKind>;
+
+/// Which match buttons the user pressed during a trial.
+pub type Response = BTreeSet<ModalityKind>;
+
+/// A presented stimulus together with how it was generated.
+#[derive(Debug, Clone)]
+pub struct Trial {
+    /// How many trials back this trial is compared against.
+    pub n: u32,
+    /// Index of the trial this one is compared against, if any.
+    pub compared_to: Option<usize>,
+    pub stimulus: Stimulus,
+    pub targets: Targets,
+    pub lures: Lures,
+    /// Set when arithmetic is active and the trial has a digit to be combined with.
+    pub problem: Option<ArithmeticProblem>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MatchOutcome {
+    Hit,
+    Miss,
+    FalseAlarm,
+    CorrectRejection,
+}
+
+impl MatchOutcome {
+    fn classify(is_target: bool, responded: bool) -> Self {
+        match (is_target, responded) {
+            (true, true) => MatchOutcome::Hit,
+            (true, false) => MatchOutcome::Miss,
+            (false, true) => MatchOutcome::FalseAlarm,
+            (false, false) => MatchOutcome::CorrectRejection,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TrialOutcome {
+    pub index: usize,
+    pub lures: Lures,
+    pub outcomes: BTreeMap<ModalityKind, MatchOutcome>,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ModalityScore {
+    pub correct: u32,
+    pub false_positive: u32,
+    pub false_negative: u32,
+    pub correct_rejection: u32,
+}
+
+impl ModalityScore {
+    fn record(&mut self, outcome: MatchOutcome) {
+        match outcome {
+            MatchOutcome::Hit => self.correct += 1,
+            MatchOutcome::Miss => self.false_negative += 1,
+            MatchOutcome::FalseAlarm => self.false_positive += 1,
+            MatchOutcome::CorrectRejection => self.correct_rejection += 1,
+        }
+    }
+
+    /// Signal-detection measures for this modality, or `None` if the session had no targets or
+    /// no non-targets for it.
+    pub fn detection(&self) -> Option<DetectionStats> {
+        let signal = self.correct + self.false_negative;
+        let noise = self.false_positive + self.correct_rejection;
+        if signal == 0 || noise == 0 {
+            return None;
+        }
+
+        // Log-linear correction keeps the z-scores finite when a rate is 0 or 1.
+        let z_hit = inverse_normal_cdf((self.correct as f64 + 0.5) / (signal as f64 + 1.0));
+        let z_false_alarm =
+            inverse_normal_cdf((self.false_positive as f64 + 0.5) / (noise as f64 + 1.0));
+
+        Some(DetectionStats {
+            hit_rate: self.correct as f32 / signal as f32,
+            false_alarm_rate: self.false_positive as f32 / noise as f32,
+            d_prime: (z_hit - z_false_alarm) as f32,
+            criterion: (-(z_hit + z_false_alarm) / 2.0) as f32,
+        })
+    }
+}
+
+/// Sensitivity (d′) and response bias (criterion c) for one modality. Positive criterion means
+/// the user holds back on responding, negative means they respond liberally.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DetectionStats {
+    pub hit_rate: f32,
+    pub false_alarm_rate: f32,
+    pub d_prime: f32,
+    pub criterion: f32,
+}
+
+/// Running tally of every active modality in a session.
+#[derive(Debug, Default, Clone)]
+pub struct Score {
+    pub modalities: BTreeMap<ModalityKind, ModalityScore>,
+}
+
+impl Score {
+    /// Correct matches as a percentage of all matches and mistakes, across all modalities.
+    pub fn percent_correct(&self) -> f32 {
+        let num_correct: u32 = self.modalities.values().map(|score| score.correct).sum();
+        let num_wrong: u32 = self
+            .modalities
+            .values()
+            .map(|score| score.false_positive + score.false_negative)
+            .sum();
+        100.0 * num_correct as f32 / (num_wrong + num_correct) as f32
+    }
+
+    /// Signal-detection measures of every modality that has them.
+    pub fn detection(&self) -> BTreeMap<ModalityKind, DetectionStats> {
+        self.modalities
+            .iter()
+            .filter_map(|(modality, score)| Some((*modality, score.detection()?)))
+            .collect()
+    }
+}
+
+/// How the engine decides which trials repeat the stimulus from N trials back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Generator {
+    /// Each modality independently repeats the N-back stimulus with the given probability
+    /// (0.0 to 1.0), otherwise it is drawn at random and may still match by chance.
+    Probabilistic { chance_of_guaranteed_match: f32 },
+    /// The whole session is planned up front in the style of Brain Workshop: exactly `single`
+    /// targets in just that one modality, and `all` targets in every channel at once, leaving out
+    /// the cross-modal comparisons. All remaining trials are non-targets in every modality.
+    ExactCount {
+        single: BTreeMap<ModalityKind, u32>,
+        all: u32,
+    },
+}
+
+/// How the trial each stimulus is compared against is chosen.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum NBackVariant {
+    /// Every trial is compared against the one exactly N back.
+    #[default]
+    Standard,
+    /// Each trial draws its own N from 1 to the level, as in Brain Workshop's variable n-back.
+    Variable,
+    /// Comparisons are mirrored within each block of N trials, as in Brain Workshop's crab-back:
+    /// at N = 3 the trials of a block look 1, 3 and 5 back.
+    Crab,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineConfig {
+    pub n: u32,
+    pub variant: NBackVariant,
+    pub total_trials: u32,
+    /// The presented channels, each with how many distinct stimuli it draws from. Cross-modal
+    /// comparisons between them are added on their own; see [`response_categories`].
+    pub modalities: BTreeMap<ModalityKind, u32>,
+    pub generator: Generator,
+    /// Probability (0.0 to 1.0) per modality that a non-target repeats the N-1 or N+1 back
+    /// stimulus. Modalities left out never get lures.
+    pub lure_rates: BTreeMap<ModalityKind, f32>,
+    pub seed: u64,
+}
+
+/// Runs a single n-back session: generates the stimulus sequence, checks responses against the
+/// stimulus N trials back and keeps score. Front-ends present each stimulus returned by
+/// [`NBackEngine::next_trial`] and report what the user pressed with [`NBackEngine::resolve`].
+///
+/// All randomness comes from one RNG seeded with `seed`, so two engines built with the same
+/// config produce the same stimulus sequence.
+#[derive(Debug, Clone)]
+pub struct NBackEngine {
+    seed: u64,
+    rng: ChaCha8Rng,
+    n: usize,
+    variant: NBackVariant,
+    total_trials: usize,
+    modalities: BTreeMap<ModalityKind, u32>,
+    categories: Vec<ModalityKind>,
+    generator: Generator,
+    lure_rates: BTreeMap<ModalityKind, f32>,
+    plan: Vec<Targets>,
+    trials: Vec<Trial>,
+    resolved: usize,
+    score: Score,
+}
+
+impl NBackEngine {
+    pub fn new(config: EngineConfig) -> Self {
+        let mut rng = ChaCha8Rng::seed_from_u64(config.seed);
+        let n = config.n as usize;
+        let total_trials = config.total_trials as usize;
+        let categories =
+            response_categories(&config.modalities.keys().copied().collect::<Vec<_>>());
+
+        let plan = match &config.generator {
+            Generator::Probabilistic { .. } => Vec::new(),
+            Generator::ExactCount { single, all } => plan_targets(
+                &mut rng,
+                total_trials.saturating_sub(n),
+                &categories
+                    .iter()
+                    .copied()
+                    .filter(|modality| *modality != ModalityKind::ARITHMETIC)
+                    .collect(),
+                single,
+                *all as usize,
+            ),
+        };
+
+        let score = Score {
+            modalities: categories
+                .iter()
+                .map(|modality| (*modality, ModalityScore::default()))
+                .collect(),
+        };
+
+        Self {
+            seed: config.seed,
+            rng,
+            n,
+            variant: config.variant,
+            total_trials,
+            modalities: config.modalities,
+            categories,
+            generator: config.generator,
+            lure_rates: config.lure_rates,
+            plan,
+            trials: Vec::with_capacity(total_trials),
+            resolved: 0,
+            score,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn score(&self) -> &Score {
+        &self.score
+    }
+
+    pub fn trials_remaining(&self) -> usize {
+        self.total_trials - self.trials.len()
+    }
+
+    /// The trial on screen, once the session has begun.
+    pub fn current_trial(&self) -> Option<&Trial> {
+        self.trials.last()
+    }
+
+    /// Whether the trial on screen has an earlier stimulus to be compared against.
+    pub fn accepts_responses(&self) -> bool {
+        self.trials.len() > self.n
+    }
+
+    /// Generates and records the next trial, or returns `None` when the session is over.
+    pub fn next_trial(&mut self) -> Option<Trial> {
+        if self.trials_remaining() == 0 {
+            return None;
+        }
+
+        let index = self.trials.len();
+        let n = self.trial_n(index);
+        let compared_to = index.checked_sub(n);
+
+        let mut lures = Lures::new();
+        let mut stimulus = match compared_to {
+            Some(compared_to) => {
+                let n_back = self.trials[compared_to].stimulus.clone();
+                let (mut stimulus, targets) = self.generate_against(index, &n_back);
+                for modality in self.modality_kinds() {
+                    if targets.iter().any(|target| target.compares().0 == modality) {
+                        continue;
+                    }
+                    let rate = self.lure_rates.get(&modality).copied().unwrap_or(0.0);
+                    let compared = compared_values(&self.categories, modality, &n_back);
+                    if let Some((lure, value)) = self.roll_lure(index, n, modality, &compared, rate)
+                    {
+                        stimulus.insert(modality, value);
+                        lures.insert(modality, lure);
+                    }
+                }
+                self.separate_positions(&mut stimulus, &targets, Some(&n_back), &mut lures);
+                self.separate_cross_modal(&mut stimulus, &targets, Some(&n_back), &mut lures);
+                stimulus
+            }
+            None => {
+                let mut stimulus = self.random_stimulus();
+                self.separate_positions(&mut stimulus, &Targets::new(), None, &mut lures);
+                self.separate_cross_modal(&mut stimulus, &Targets::new(), None, &mut lures);
+                stimulus
+            }
+        };
+
+        let problem = match compared_to {
+            Some(compared_to) => self.pose_problem(compared_to, &mut stimulus),
+            None => None,
+        };
+
+        let targets = match compared_to {
+            Some(compared_to) => {
+                let n_back = &self.trials[compared_to].stimulus;
+                self.categories
+                    .iter()
+                    .copied()
+                    .filter(|modality| *modality != ModalityKind::ARITHMETIC)
+                    .filter(|modality| {
+                        let (current, back) = modality.compares();
+                        n_back.contains_key(&back) && stimulus.get(&current) == n_back.get(&back)
+                    })
+                    .collect()
+            }
+            None => Targets::new(),
+        };
+
+        let trial = Trial {
+            n: n as u32,
+            compared_to,
+            stimulus,
+            targets,
+            lures,
+            problem,
+        };
+        self.trials.push(trial.clone());
+        Some(trial)
+    }
+
+    /// Scores the trial on screen against the user's response and, in arithmetic, the typed
+    /// answer. Returns `None` if there is no trial to score, either because it has no N-back
+    /// comparison or it was already resolved.
+    pub fn resolve(&mut self, response: &Response, answer: Option<i32>) -> Option<TrialOutcome> {
+        let index = self.trials.len().checked_sub(1)?;
+        if self.resolved > index {
+            return None;
+        }
+        self.resolved = index + 1;
+
+        let trial = &self.trials[index];
+        trial.compared_to?;
+
+        let outcome = TrialOutcome {
+            index,
+            lures: trial.lures.clone(),
+            outcomes: self
+                .categories
+                .iter()
+                .map(|modality| {
+                    let outcome = match (*modality, trial.problem) {
+                        // Every arithmetic trial calls for an answer, so it is either a hit or a
+                        // miss.
+                        (ModalityKind::ARITHMETIC, Some(problem)) => {
+                            MatchOutcome::classify(true, answer == Some(problem.answer))
+                        }
+                        _ => MatchOutcome::classify(
+                            trial.targets.contains(modality),
+                            response.contains(modality),
+                        ),
+                    };
+                    (*modality, outcome)
+                })
+                .collect(),
+        };
+
+        for (modality, match_outcome) in &outcome.outcomes {
+            self.score
+                .modalities
+                .entry(*modality)
+                .or_default()
+                .record(*match_outcome);
+        }
+
+        Some(outcome)
+    }
+
+    fn modality_kinds(&self) -> Vec<ModalityKind> {
+        self.modalities.keys().copied().collect()
+    }
+
+    fn random_stimulus(&mut self) -> Stimulus {
+        self.modalities
+            .iter()
+            .map(|(modality, size)| (*modality, self.rng.gen_range(0..*size)))
+            .collect()
+    }
+
+    /// Position streams share the grid, so their squares must land on different cells. Targets
+    /// keep their cell, and any other stream that lands on a taken one is moved to a free cell
+    /// that also differs from its N-back cell.
+    fn separate_positions(
+        &mut self,
+        stimulus: &mut Stimulus,
+        targets: &Targets,
+        n_back: Option<&Stimulus>,
+        lures: &mut Lures,
+    ) {
+        let mut taken = targets
+            .iter()
+            .filter(|modality| modality.is_position())
+            .map(|modality| stimulus[modality])
+            .collect::<BTreeSet<_>>();
+        let streams = stimulus
+            .keys()
+            .copied()
+            .filter(|modality| modality.is_position() && !targets.contains(modality))
+            .collect::<Vec<_>>();
+
+        for modality in streams {
+            if taken.insert(stimulus[&modality]) {
+                continue;
+            }
+
+            let n_back_value = n_back.map(|n_back| n_back[&modality]);
+            let free = (0..self.modalities[&modality])
+                .filter(|value| !taken.contains(value) && Some(*value) != n_back_value)
+                .collect::<Vec<_>>();
+            if let Some(value) = free.choose(&mut self.rng) {
+                taken.insert(*value);
+                stimulus.insert(modality, *value);
+                lures.remove(&modality);
+            }
+        }
+    }
+
+    /// With exact counts, two channels compared across each other never show the same value on
+    /// one trial. Otherwise a trial N back with the same letter seen and heard would turn every
+    /// match of one channel into a match of the comparison as well, and the planned counts would
+    /// drift. The channel not held by a target is redrawn, avoiding every value it is compared
+    /// against.
+    fn separate_cross_modal(
+        &mut self,
+        stimulus: &mut Stimulus,
+        targets: &Targets,
+        n_back: Option<&Stimulus>,
+        lures: &mut Lures,
+    ) {
+        if !matches!(self.generator, Generator::ExactCount { .. }) {
+            return;
+        }
+
+        let pairs = self
+            .categories
+            .iter()
+            .filter(|category| category.is_cross_modal())
+            .map(ModalityKind::compares)
+            .collect::<Vec<_>>();
+        for (channel, partner) in pairs {
+            if stimulus[&channel] != stimulus[&partner] {
+                continue;
+            }
+
+            let held = |modality: ModalityKind| {
+                targets.iter().any(|target| target.compares().0 == modality)
+            };
+            let (redrawn, kept) = if held(channel) {
+                (partner, channel)
+            } else {
+                (channel, partner)
+            };
+            if held(redrawn) {
+                continue;
+            }
+
+            let mut excluded = n_back.map_or_else(BTreeSet::new, |n_back| {
+                compared_values(&self.categories, redrawn, n_back)
+            });
+            excluded.insert(stimulus[&kept]);
+            let value = random_except(
+                &mut self.rng,
+                self.modalities[&redrawn],
+                &excluded,
+                stimulus[&kept],
+            );
+            stimulus.insert(redrawn, value);
+            lures.remove(&redrawn);
+        }
+    }
+
+    /// Picks the operation for an arithmetic trial compared against trial `compared_to`. For
+    /// division the current digit is redrawn from the divisors of the N-back digit, so the
+    /// answer is always a whole number.
+    fn pose_problem(
+        &mut self,
+        compared_to: usize,
+        stimulus: &mut Stimulus,
+    ) -> Option<ArithmeticProblem> {
+        let size = *self.modalities.get(&ModalityKind::ARITHMETIC)?;
+        let n_back = self.trials[compared_to].stimulus[&ModalityKind::ARITHMETIC];
+        let operation = Operation::random(&mut self.rng);
+
+        if operation == Operation::Divide {
+            let divisors = (1..size)
+                .filter(|divisor| n_back.is_multiple_of(*divisor))
+                .collect::<Vec<_>>();
+            stimulus.insert(
+                ModalityKind::ARITHMETIC,
+                *divisors.choose(&mut self.rng).unwrap_or(&1),
+            );
+        }
+
+        Some(ArithmeticProblem {
+            operation,
+            answer: operation.apply(n_back, stimulus[&ModalityKind::ARITHMETIC]),
+        })
+    }
+
+    /// Picks how many trials back trial `index` is compared against. Trials before the level
+    /// has been reached have nothing to compare against in any variant.
+    fn trial_n(&mut self, index: usize) -> usize {
+        match self.variant {
+            NBackVariant::Variable if index >= self.n => self.rng.gen_range(1..=self.n),
+            NBackVariant::Crab if index >= self.n => 1 + 2 * (index % self.n),
+            _ => self.n,
+        }
+    }
+
+    /// Generates trial `index` according to the generator, returning it along with which
+    /// modalities were made to repeat `n_back`.
+    fn generate_against(&mut self, index: usize, n_back: &Stimulus) -> (Stimulus, Targets) {
+        match self.generator {
+            Generator::Probabilistic {
+                chance_of_guaranteed_match,
+            } => {
+                let mut stimulus = self.random_stimulus();
+                let mut targets = Targets::new();
+                for modality in self.categories.clone() {
+                    if modality == ModalityKind::ARITHMETIC {
+                        continue;
+                    }
+                    if self.rng.gen::<f32>() < chance_of_guaranteed_match {
+                        targets.insert(modality);
+                    }
+                }
+                for modality in &targets {
+                    let (current, back) = modality.compares();
+                    stimulus.insert(current, n_back[&back]);
+                }
+                (stimulus, targets)
+            }
+            Generator::ExactCount { .. } => {
+                let targets = self.plan[index - self.n].clone();
+                let stimulus = self
+                    .modalities
+                    .iter()
+                    .map(|(modality, size)| {
+                        // Any target reading this channel now fixes its value, the channel's own
+                        // match first.
+                        let target = targets
+                            .iter()
+                            .map(ModalityKind::compares)
+                            .find(|(current, _)| current == modality);
+                        let value = if *modality == ModalityKind::ARITHMETIC {
+                            self.rng.gen_range(0..*size)
+                        } else if let Some((_, back)) = target {
+                            n_back[&back]
+                        } else {
+                            // Left out of every category it takes part in, so no target appears
+                            // beyond the planned ones.
+                            let compared = compared_values(&self.categories, *modality, n_back);
+                            random_except(&mut self.rng, *size, &compared, n_back[modality])
+                        };
+                        (*modality, value)
+                    })
+                    .collect();
+                (stimulus, targets)
+            }
+        }
+    }
+
+    /// With probability `rate`, picks the `modality` value from N-1 or N+1 trials back to use as
+    /// a lure for trial `index`. Only values outside `compared`, the N-back values the channel is
+    /// matched against, qualify, so a lure is never a target.
+    fn roll_lure(
+        &mut self,
+        index: usize,
+        n: usize,
+        modality: ModalityKind,
+        compared: &BTreeSet<u32>,
+        rate: f32,
+    ) -> Option<(Lure, u32)> {
+        if rate <= 0.0 || self.rng.gen::<f32>() >= rate {
+            return None;
+        }
+
+        let candidates = [(Lure::NMinusOne, n - 1), (Lure::NPlusOne, n + 1)]
+            .into_iter()
+            .filter(|(_, distance)| *distance > 0 && *distance <= index)
+            .filter_map(|(lure, distance)| {
+                let value = *self.trials[index - distance].stimulus.get(&modality)?;
+                Some((lure, value))
+            })
+            .filter(|(_, lure_value)| !compared.contains(lure_value))
+            .collect::<Vec<_>>();
+
+        candidates.choose(&mut self.rng).copied()
+    }
+}
+
+/// Lays out `scored_trials` target plans with the requested number of each kind in random order.
+/// If the requested targets do not fit, targets in every modality are kept first, then the
+/// single-modality targets in modality order.
+///
+/// A channel can only hold one value per trial, so the targets in every modality leave out the
+/// cross-modal comparisons: with both letter channels matching their own N-back letters, they
+/// can't also match each other's.
+fn plan_targets(
+    rng: &mut ChaCha8Rng,
+    scored_trials: usize,
+    modalities: &Targets,
+    single: &BTreeMap<ModalityKind, u32>,
+    all: usize,
+) -> Vec<Targets> {
+    let every = modalities
+        .iter()
+        .copied()
+        .filter(|modality| !modality.is_cross_modal())
+        .collect::<Targets>();
+    let mut plan = vec![every; all.min(scored_trials)];
+    for modality in modalities {
+        let count = single.get(modality).copied().unwrap_or(0) as usize;
+        let count = count.min(scored_trials - plan.len());
+        plan.resize(plan.len() + count, Targets::from([*modality]));
+    }
+    plan.resize(scored_trials, Targets::new());
+    plan.shuffle(rng);
+    plan
+}
+
+/// The N-back values that a value of `modality` now would match in one of `categories`: its own
+/// N-back value, plus the other channel's for each cross-modal comparison reading it.
+fn compared_values(
+    categories: &[ModalityKind],
+    modality: ModalityKind,
+    n_back: &Stimulus,
+) -> BTreeSet<u32> {
+    categories
+        .iter()
+        .map(ModalityKind::compares)
+        .filter(|(current, _)| *current == modality)
+        .filter_map(|(_, back)| n_back.get(&back).copied())
+        .collect()
+}
+
+/// Draws a stimulus index below `size` that is not in `excluded`. If `excluded` covers the whole
+/// set, only `fallback` is avoided.
+fn random_except(rng: &mut ChaCha8Rng, size: u32, excluded: &BTreeSet<u32>, fallback: u32) -> u32 {
+    let excluded = if (0..size).all(|value| excluded.contains(&value)) {
+        BTreeSet::from([fallback])
+    } else {
+        excluded.clone()
+    };
+    loop {
+        let value = rng.gen_range(0..size);
+        if !excluded.contains(&value) {
+            return value;
+        }
+    }
+}
+
+/// Quantile function of the standard normal distribution, using Acklam's rational
+/// approximation (relative error below 1.2e-9).
+fn inverse_normal_cdf(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e1,
+        2.209460984245205e2,
+        -2.759285104469687e2,
+        1.38357751867269e2,
+        -3.066479806614716e1,
+        2.506628277459239,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e1,
+        1.615858368580409e2,
+        -1.556989798598866e2,
+        6.680131188771972e1,
+        -1.328068155288572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-3,
+        -3.223964580411365e-1,
+        -2.400758277161838,
+        -2.549732539343734,
+        4.374664141464968,
+        2.938163982698783,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-3,
+        3.224671290700398e-1,
+        2.445134137142996,
+        3.754408661907416,
+    ];
+    const P_LOW: f64 = 0.02425;
+
+    if p < P_LOW {
+        let q = (-2.0 * p.ln()).sqrt();
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    } else if p <= 1.0 - P_LOW {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    } else {
+        -inverse_normal_cdf(1.0 - p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(modalities: &[ModalityKind], generator: Generator) -> EngineConfig {
+        EngineConfig {
+            n: 2,
+            variant: NBackVariant::Standard,
+            total_trials: 22,
+            modalities: modalities.iter().map(|modality| (*modality, 8)).collect(),
+            generator,
+            lure_rates: BTreeMap::new(),
+            seed: 1,
+        }
+    }
+
+    fn no_targets() -> Generator {
+        Generator::ExactCount {
+            single: BTreeMap::new(),
+            all: 0,
+        }
+    }
+
+    /// Plays through every trial, pressing `response` on each, and returns the outcomes.
+    fn play(engine: &mut NBackEngine, response: &Response) -> Vec<TrialOutcome> {
+        let mut outcomes = Vec::new();
+        while engine.next_trial().is_some() {
+            outcomes.extend(engine.resolve(response, None));
+        }
+        outcomes
+    }
+
+    #[test]
+    fn resolve_scores_targets_as_hits_or_misses() {
+        let generator = Generator::Probabilistic {
+            chance_of_guaranteed_match: 1.0,
+        };
+        let modalities = [ModalityKind::POSITION];
+
+        let mut engine = NBackEngine::new(config(&modalities, generator.clone()));
+        let outcomes = play(&mut engine, &Response::from(modalities));
+        assert_eq!(outcomes.len(), 20);
+        assert!(outcomes
+            .iter()
+            .all(|outcome| outcome.outcomes[&ModalityKind::POSITION] == MatchOutcome::Hit));
+
+        let mut engine = NBackEngine::new(config(&modalities, generator));
+        let outcomes = play(&mut engine, &Response::new());
+        assert!(outcomes
+            .iter()
+            .all(|outcome| outcome.outcomes[&ModalityKind::POSITION] == MatchOutcome::Miss));
+        let score = engine.score().modalities[&ModalityKind::POSITION];
+        assert_eq!((score.correct, score.false_negative), (0, 20));
+    }
+
+    #[test]
+    fn resolve_scores_non_targets_as_false_alarms_or_correct_rejections() {
+        let modalities = [ModalityKind::POSITION, ModalityKind::AUDIO];
+
+        let mut engine = NBackEngine::new(config(&modalities, no_targets()));
+        let outcomes = play(&mut engine, &Response::from([ModalityKind::AUDIO]));
+        for outcome in &outcomes {
+            assert_eq!(
+                outcome.outcomes[&ModalityKind::POSITION],
+                MatchOutcome::CorrectRejection
+            );
+            assert_eq!(
+                outcome.outcomes[&ModalityKind::AUDIO],
+                MatchOutcome::FalseAlarm
+            );
+        }
+        let score = engine.score().modalities[&ModalityKind::AUDIO];
+        assert_eq!((score.false_positive, score.correct_rejection), (20, 0));
+    }
+
+    #[test]
+    fn resolve_skips_trials_without_a_comparison_and_trials_already_scored() {
+        let mut engine = NBackEngine::new(config(&[ModalityKind::POSITION], no_targets()));
+        for _ in 0..2 {
+            engine.next_trial();
+            assert!(engine.resolve(&Response::new(), None).is_none());
+        }
+        engine.next_trial();
+        assert!(engine.resolve(&Response::new(), None).is_some());
+        assert!(engine.resolve(&Response::new(), None).is_none());
+    }
+
+    #[test]
+    fn resolve_checks_the_typed_arithmetic_answer() {
+        let mut engine = NBackEngine::new(config(&[ModalityKind::ARITHMETIC], no_targets()));
+        engine.next_trial();
+        engine.next_trial();
+        let problem = engine.next_trial().unwrap().problem.unwrap();
+        let outcome = engine
+            .resolve(&Response::new(), Some(problem.answer))
+            .unwrap();
+        assert_eq!(
+            outcome.outcomes[&ModalityKind::ARITHMETIC],
+            MatchOutcome::Hit
+        );
+
+        let problem = engine.next_trial().unwrap().problem.unwrap();
+        let outcome = engine
+            .resolve(&Response::new(), Some(problem.answer + 1))
+            .unwrap();
+        assert_eq!(
+            outcome.outcomes[&ModalityKind::ARITHMETIC],
+            MatchOutcome::Miss
+        );
+    }
+
+    fn sequence(config: EngineConfig) -> Vec<Stimulus> {
+        let mut engine = NBackEngine::new(config);
+        std::iter::from_fn(|| engine.next_trial())
+            .map(|trial| trial.stimulus)
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_sequence() {
+        let modalities = [
+            ModalityKind::POSITION,
+            ModalityKind::AUDIO,
+            ModalityKind::COLOR,
+        ];
+        let config = EngineConfig {
+            seed: u64::MAX - 7,
+            lure_rates: BTreeMap::from([(ModalityKind::POSITION, 0.5)]),
+            ..config(
+                &modalities,
+                Generator::Probabilistic {
+                    chance_of_guaranteed_match: 0.25,
+                },
+            )
+        };
+        assert_eq!(sequence(config.clone()), sequence(config.clone()));
+        assert_ne!(
+            sequence(config.clone()),
+            sequence(EngineConfig { seed: 2, ..config })
+        );
+    }
+
+    #[test]
+    fn modality_kinds_are_stored_by_id() {
+        let smell = ModalityKind::new("Smell");
+        let kinds = BTreeSet::from([smell, ModalityKind::SPATIAL, ModalityKind::POSITION]);
+        let saved = ron::to_string(&kinds).unwrap();
+        assert_eq!(saved, r#"["Position","Spatial","Smell"]"#);
+
+        let loaded: BTreeSet<ModalityKind> = ron::from_str(&saved).unwrap();
+        assert_eq!(loaded, kinds);
+        assert_eq!(ModalityKind::from_id("Smell"), smell);
+        assert_eq!(smell.label(), "Smell");
+    }
+
+    #[test]
+    fn a_channel_added_by_a_plugin_is_matched_like_any_other() {
+        let smell = ModalityKind::new("Smell");
+        let generator = Generator::Probabilistic {
+            chance_of_guaranteed_match: 1.0,
+        };
+        let mut engine = NBackEngine::new(config(&[ModalityKind::POSITION, smell], generator));
+        let outcomes = play(&mut engine, &Response::from([smell]));
+        assert!(outcomes
+            .iter()
+            .all(|outcome| outcome.outcomes[&smell] == MatchOutcome::Hit));
+    }
+
+    #[test]
+    fn a_saved_config_replays_the_same_sequence() {
+        let modalities = [ModalityKind::POSITION, ModalityKind::AUDIO];
+        let config = EngineConfig {
+            seed: u64::MAX - 7,
+            lure_rates: BTreeMap::from([(ModalityKind::AUDIO, 0.3)]),
+            ..config(
+                &modalities,
+                Generator::ExactCount {
+                    single: BTreeMap::from([(ModalityKind::POSITION, 3), (ModalityKind::AUDIO, 2)]),
+                    all: 2,
+                },
+            )
+        };
+        let saved = ron::to_string(&config).unwrap();
+        let loaded: EngineConfig = ron::from_str(&saved).unwrap();
+        assert_eq!(sequence(loaded), sequence(config));
+    }
+
+    fn count(plan: &[Targets], targets: &Targets) -> usize {
+        plan.iter().filter(|planned| *planned == targets).count()
+    }
+
+    #[test]
+    fn plan_targets_lays_out_the_requested_counts() {
+        let modalities = Targets::from([ModalityKind::POSITION, ModalityKind::AUDIO]);
+        let single = BTreeMap::from([(ModalityKind::POSITION, 4), (ModalityKind::AUDIO, 3)]);
+        let plan = plan_targets(
+            &mut ChaCha8Rng::seed_from_u64(1),
+            20,
+            &modalities,
+            &single,
+            2,
+        );
+
+        assert_eq!(plan.len(), 20);
+        assert_eq!(count(&plan, &modalities), 2);
+        assert_eq!(count(&plan, &Targets::from([ModalityKind::POSITION])), 4);
+        assert_eq!(count(&plan, &Targets::from([ModalityKind::AUDIO])), 3);
+        assert_eq!(count(&plan, &Targets::new()), 11);
+    }
+
+    #[test]
+    fn plan_targets_keeps_targets_in_every_modality_first_when_they_do_not_fit() {
+        let modalities = Targets::from([ModalityKind::POSITION, ModalityKind::AUDIO]);
+        let single = BTreeMap::from([(ModalityKind::POSITION, 4), (ModalityKind::AUDIO, 3)]);
+        let plan = plan_targets(
+            &mut ChaCha8Rng::seed_from_u64(1),
+            8,
+            &modalities,
+            &single,
+            2,
+        );
+
+        assert_eq!(plan.len(), 8);
+        assert_eq!(count(&plan, &modalities), 2);
+        assert_eq!(count(&plan, &Targets::from([ModalityKind::AUDIO])), 2);
+        assert_eq!(count(&plan, &Targets::from([ModalityKind::POSITION])), 4);
+    }
+
+    #[test]
+    fn exact_count_sessions_have_exactly_the_planned_targets() {
+        let modalities = [
+            ModalityKind::POSITION,
+            ModalityKind::AUDIO,
+            ModalityKind::COLOR,
+        ];
+        let generator = Generator::ExactCount {
+            single: modalities.iter().map(|modality| (*modality, 4)).collect(),
+            all: 2,
+        };
+        for seed in 0..50 {
+            let mut engine = NBackEngine::new(EngineConfig {
+                seed,
+                total_trials: 42,
+                lure_rates: modalities.iter().map(|modality| (*modality, 0.5)).collect(),
+                ..config(&modalities, generator.clone())
+            });
+            let trials = std::iter::from_fn(|| engine.next_trial()).collect::<Vec<_>>();
+            for modality in modalities {
+                let targets = trials
+                    .iter()
+                    .filter(|trial| trial.targets.contains(&modality))
+                    .count();
+                assert_eq!(targets, 6, "seed {seed}, {modality:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn lures_are_never_targets() {
+        let modalities = [
+            ModalityKind::POSITION,
+            ModalityKind::AUDIO,
+            ModalityKind::VISUAL_LETTER,
+        ];
+        let lure_rates = modalities
+            .iter()
+            .map(|modality| (*modality, 1.0))
+            .collect::<BTreeMap<_, _>>();
+        let generators = [
+            Generator::Probabilistic {
+                chance_of_guaranteed_match: 0.3,
+            },
+            Generator::ExactCount {
+                single: modalities.iter().map(|modality| (*modality, 3)).collect(),
+                all: 1,
+            },
+        ];
+        let mut lures = 0;
+        for generator in generators {
+            for seed in 0..50 {
+                let mut engine = NBackEngine::new(EngineConfig {
+                    seed,
+                    n: 3,
+                    lure_rates: lure_rates.clone(),
+                    ..config(&modalities, generator.clone())
+                });
+                while let Some(trial) = engine.next_trial() {
+                    for modality in trial.lures.keys() {
+                        lures += 1;
+                        assert!(
+                            !trial
+                                .targets
+                                .iter()
+                                .any(|target| target.compares().0 == *modality),
+                            "seed {seed}: {modality:?} is both a lure and a target in {trial:?}"
+                        );
+                    }
+                }
+            }
+        }
+        assert!(lures > 0);
+    }
+
+    #[test]
+    fn inverse_normal_cdf_matches_known_quantiles() {
+        assert!(inverse_normal_cdf(0.5).abs() < 1e-9);
+        assert!((inverse_normal_cdf(0.975) - 1.959964).abs() < 1e-6);
+        assert!((inverse_normal_cdf(0.01) + 2.326348).abs() < 1e-6);
+    }
+
+    #[test]
+    fn detection_matches_known_values() {
+        let score = ModalityScore {
+            correct: 15,
+            false_negative: 5,
+            false_positive: 3,
+            correct_rejection: 27,
+        };
+        let detection = score.detection().unwrap();
+        assert_eq!(detection.hit_rate, 0.75);
+        assert_eq!(detection.false_alarm_rate, 0.1);
+        assert!((detection.d_prime - 1.848716).abs() < 1e-5);
+        assert!((detection.criterion - 0.286874).abs() < 1e-5);
+
+        // Symmetric rates mean no bias.
+        let score = ModalityScore {
+            correct: 8,
+            false_negative: 2,
+            false_positive: 2,
+            correct_rejection: 8,
+        };
+        let detection = score.detection().unwrap();
+        assert!((detection.d_prime - 1.495717).abs() < 1e-5);
+        assert!(detection.criterion.abs() < 1e-6);
+    }
+
+    #[test]
+    fn detection_needs_targets_and_non_targets() {
+        let no_targets = ModalityScore {
+            false_positive: 1,
+            correct_rejection: 9,
+            ..Default::default()
+        };
+        let no_non_targets = ModalityScore {
+            correct: 5,
+            ..Default::default()
+        };
+        assert!(no_targets.detection().is_none());
+        assert!(no_non_targets.detection().is_none());
+    }
+
+    #[test]
+    fn crab_back_looks_one_three_and_five_back_at_n_three() {
+        let mut engine = NBackEngine::new(EngineConfig {
+            n: 3,
+            variant: NBackVariant::Crab,
+            ..config(&[ModalityKind::POSITION], no_targets())
+        });
+        let trials = std::iter::from_fn(|| engine.next_trial()).collect::<Vec<_>>();
+
+        assert!(trials[..3].iter().all(|trial| trial.compared_to.is_none()));
+        let offsets = trials[3..]
+            .iter()
+            .enumerate()
+            .map(|(index, trial)| (index + 3 - trial.compared_to.unwrap(), trial.n as usize))
+            .collect::<Vec<_>>();
+        for (index, (offset, n)) in offsets.into_iter().enumerate() {
+            assert_eq!(offset, n);
+            assert_eq!(offset, [1, 3, 5][index % 3]);
+        }
+    }
+
+    #[test]
+    fn exact_counts_hold_with_cross_modal_comparisons() {
+        let modalities = [ModalityKind::AUDIO, ModalityKind::VISUAL_LETTER];
+        let single = response_categories(&modalities)
+            .into_iter()
+            .map(|modality| (modality, 3))
+            .collect::<BTreeMap<_, _>>();
+        for seed in 0..100 {
+            let mut engine = NBackEngine::new(EngineConfig {
+                seed,
+                total_trials: 32,
+                lure_rates: modalities.iter().map(|modality| (*modality, 0.5)).collect(),
+                ..config(
+                    &modalities,
+                    Generator::ExactCount {
+                        single: single.clone(),
+                        all: 2,
+                    },
+                )
+            });
+            let trials = std::iter::from_fn(|| engine.next_trial()).collect::<Vec<_>>();
+            for modality in single.keys() {
+                let expected = if modality.is_cross_modal() { 3 } else { 5 };
+                let targets = trials
+                    .iter()
+                    .filter(|trial| trial.targets.contains(modality))
+                    .count();
+                assert_eq!(targets, expected, "seed {seed}, {modality:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{LayoutKind, SettingValues, CELL_SIZE, GRID_LENGTH};
+use bevy::prelude::*;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::f32::consts::TAU;
+
+/// Seed of the scatter layout, so the scattered positions stay put from session to session.
+const SCATTER_SEED: u64 = 0x5CA7;
+
+/// Where the positions of the session sit on screen. Position stimuli are indexes into `cells`.
+#[derive(Resource, Clone, Debug)]
+pub struct Layout {
+    /// Centre of every position, relative to the middle of the board.
+    pub cells: Vec<Vec2>,
+    /// Space taken by each position; targets are drawn a little smaller.
+    pub cell_size: f32,
+    /// Rectangular layouts are drawn as a grid, the others as an outline around each position.
+    pub grid: Option<Grid>,
+}
+
+impl Layout {
+    /// Builds the layout picked in the settings, which only fails for a custom layout file that
+    /// can't be read.
+    pub fn from_settings(settings: &SettingValues) -> Result<Self, String> {
+        match settings.layout {
+            LayoutKind::Grid => Ok(Layout::grid(Grid::from_settings(settings))),
+            LayoutKind::Ring => Ok(Layout::ring(8)),
+            LayoutKind::Hexagon => Ok(Layout::hexagon()),
+            LayoutKind::Scatter => Ok(Layout::scatter(9)),
+            LayoutKind::Custom => Layout::from_file(&settings.custom_layout),
+        }
+    }
+
+    pub fn grid(grid: Grid) -> Self {
+        let cell_size = grid.cell_size();
+        let cells = (0..grid.cell_count())
+            .map(|cell| {
+                let column = (cell % grid.columns) as f32;
+                let row = (cell / grid.columns) as f32;
+                Vec2::new(
+                    (column - (grid.columns - 1) as f32 / 2.0) * cell_size,
+                    ((grid.rows - 1) as f32 / 2.0 - row) * cell_size,
+                )
+            })
+            .collect();
+        Layout {
+            cells,
+            cell_size,
+            grid: Some(grid),
+        }
+    }
+
+    /// `count` positions evenly spaced on a circle, clockwise from the top.
+    pub fn ring(count: u32) -> Self {
+        let radius = (GRID_LENGTH - CELL_SIZE) / 2.0 + 0.1 * CELL_SIZE;
+        let cells = (0..count)
+            .map(|cell| {
+                let angle = TAU * cell as f32 / count as f32;
+                Vec2::new(angle.sin(), angle.cos()) * radius
+            })
+            .collect();
+        Layout {
+            cells,
+            cell_size: 0.75 * CELL_SIZE,
+            grid: None,
+        }
+    }
+
+    /// A centre position ringed by six neighbours, as on a honeycomb.
+    pub fn hexagon() -> Self {
+        let cells = std::iter::once(Vec2::ZERO)
+            .chain((0..6).map(|cell| {
+                let angle = TAU * (cell as f32 + 0.5) / 6.0;
+                Vec2::new(angle.sin(), angle.cos()) * CELL_SIZE
+            }))
+            .collect();
+        Layout {
+            cells,
+            cell_size: 0.85 * CELL_SIZE,
+            grid: None,
+        }
+    }
+
+    /// `count` positions scattered over the board, the same every time.
+    pub fn scatter(count: u32) -> Self {
+        let cell_size = 0.75 * CELL_SIZE;
+        let reach = (GRID_LENGTH - cell_size) / 2.0;
+        let mut rng = ChaCha8Rng::seed_from_u64(SCATTER_SEED);
+        let mut cells: Vec<Vec2> = Vec::new();
+        while cells.len() < count as usize {
+            let cell = Vec2::new(rng.gen_range(-reach..=reach), rng.gen_range(-reach..=reach));
+            if cells
+                .iter()
+                .all(|other| other.distance(cell) > 1.1 * cell_size)
+            {
+                cells.push(cell);
+            }
+        }
+        Layout {
+            cells,
+            cell_size,
+            grid: None,
+        }
+    }
+
+    /// Reads a layout file: one position per line as `x y`, in cells from the middle of the
+    /// board with y pointing up. Blank lines and lines starting with `#` are skipped.
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+        let mut cells = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let coordinates = line
+                .split_whitespace()
+                .map(str::parse::<f32>)
+                .collect::<Result<Vec<_>, _>>();
+            match coordinates.as_deref() {
+                Ok([x, y]) => cells.push(Vec2::new(*x, *y) * CELL_SIZE),
+                _ => return Err(format!("line {} is not an `x y` pair", number + 1)),
+            }
+        }
+        if cells.len() < 2 {
+            return Err("a layout needs at least 2 positions".to_string());
+        }
+        Ok(Layout {
+            cells,
+            cell_size: CELL_SIZE,
+            grid: None,
+        })
+    }
+
+    pub fn cell_count(&self) -> u32 {
+        self.cells.len() as u32
+    }
+
+    /// The position closest to the middle of the board.
+    pub fn center(&self) -> u32 {
+        (0..self.cell_count())
+            .min_by(|a, b| {
+                self.cells[*a as usize]
+                    .length()
+                    .total_cmp(&self.cells[*b as usize].length())
+            })
+            .unwrap_or(0)
+    }
+}
+
+/// A rectangular board: `columns` by `rows` cells, numbered row by row from the top left. Boards
+/// wider or taller than 3 get smaller cells so they take the same space.
+#[derive(Clone, Copy, Debug)]
+pub struct Grid {
+    pub columns: u32,
+    pub rows: u32,
+}
+
+impl Grid {
+    pub fn from_settings(settings: &SettingValues) -> Self {
+        Grid {
+            columns: settings.grid_columns.max(1),
+            rows: settings.grid_rows.max(1),
+        }
+    }
+
+    pub fn cell_count(&self) -> u32 {
+        self.columns * self.rows
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        GRID_LENGTH / self.columns.max(self.rows).max(3) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `contents` to a layout file of its own and reads it back.
+    fn read_layout(name: &str, contents: &str) -> Result<Layout, String> {
+        let path = std::env::temp_dir().join(format!("dual-n-back-layout-{name}.txt"));
+        std::fs::write(&path, contents).unwrap();
+        let layout = Layout::from_file(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        layout
+    }
+
+    #[test]
+    fn from_file_reads_positions_in_cells() {
+        let layout = read_layout("good", "# comment\n\n0 1\n-1.5 0\n").unwrap();
+        assert_eq!(
+            layout.cells,
+            vec![Vec2::new(0.0, CELL_SIZE), Vec2::new(-1.5 * CELL_SIZE, 0.0)]
+        );
+        assert!(layout.grid.is_none());
+    }
+
+    #[test]
+    fn from_file_reads_the_bundled_example() {
+        assert!(Layout::from_file("assets/layouts/custom.txt").is_ok());
+    }
+
+    #[test]
+    fn from_file_rejects_lines_that_are_not_pairs() {
+        for (name, line) in [("one", "1"), ("three", "1 2 3"), ("word", "1 up")] {
+            assert_eq!(
+                read_layout(name, &format!("0 0\n{line}\n1 1\n")).unwrap_err(),
+                "line 2 is not an `x y` pair"
+            );
+        }
+    }
+
+    #[test]
+    fn from_file_rejects_fewer_than_two_positions() {
+        assert_eq!(
+            read_layout("single", "# just one\n0 0\n").unwrap_err(),
+            "a layout needs at least 2 positions"
+        );
+    }
+
+    #[test]
+    fn from_file_rejects_a_missing_file() {
+        assert!(Layout::from_file("assets/layouts/missing.txt").is_err());
+    }
+}
@@ -0,0 +1,343 @@
+use crate::{
+    colors, despawn_screen, AppState, DigitPack, EngineConfig, Grid, Layout, ModalityKind,
+    ModalityRegistry, SessionLog, SettingValues, SoundPack, StimulusSets, Tone, ToneSet,
+    DIGIT_SOUND_PACK,
+};
+use bevy::{
+    asset::{HandleId, LoadState},
+    prelude::*,
+};
+use bevy_pkv::PkvStore;
+use std::io::Cursor;
+
+const FONT: &str = "fonts/FiraSans-Bold.ttf";
+
+/// Gets everything a session needs ready between the menu and the session, and stops on an
+/// error screen when something is missing or broken.
+pub struct LoadingPlugin;
+
+impl Plugin for LoadingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LoadingErrors>()
+            .init_resource::<SessionAssets>()
+            .add_systems(
+                OnEnter(AppState::Loading),
+                (
+                    setup_loading,
+                    setup_replay,
+                    (
+                        setup_layout,
+                        setup_sound_pack,
+                        setup_digit_pack,
+                        setup_tones,
+                    ),
+                    apply_deferred,
+                    setup_session_assets,
+                )
+                    .chain(),
+            )
+            .add_systems(Update, loading_system.run_if(in_state(AppState::Loading)))
+            .add_systems(OnExit(AppState::Loading), despawn_screen::<OnLoadingScreen>)
+            .add_systems(OnEnter(AppState::Menu), end_replay);
+    }
+}
+
+#[derive(Component)]
+pub struct OnLoadingScreen;
+
+#[derive(Component)]
+pub struct LoadingLabel;
+
+/// Why the session can't start, one line per problem.
+#[derive(Debug, Default, Resource)]
+pub struct LoadingErrors(pub Vec<String>);
+
+/// What the stimulus sequence of the session being replayed was generated from.
+#[derive(Debug, Resource)]
+pub struct Replay(pub EngineConfig);
+
+/// Every font, recording and image the session may use, held so they stay loaded until the
+/// next session.
+#[derive(Debug, Default, Resource)]
+pub struct SessionAssets {
+    pub fonts: Vec<Handle<Font>>,
+    pub sounds: Vec<Handle<AudioSource>>,
+    pub images: Vec<Handle<Image>>,
+}
+
+impl SessionAssets {
+    fn handles(&self) -> impl Iterator<Item = HandleId> + '_ {
+        self.fonts
+            .iter()
+            .map(|handle| handle.id())
+            .chain(self.sounds.iter().map(|handle| handle.id()))
+            .chain(self.images.iter().map(|handle| handle.id()))
+    }
+}
+
+pub fn setup_loading(
+    mut commands: Commands,
+    mut errors: ResMut<LoadingErrors>,
+    asset_server: Res<AssetServer>,
+) {
+    errors.0.clear();
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    position_type: PositionType::Absolute,
+                    ..default()
+                },
+                ..default()
+            },
+            OnLoadingScreen,
+        ))
+        .with_children(|builder| {
+            builder.spawn((
+                TextBundle::from_section(
+                    "Loading...",
+                    TextStyle {
+                        font: asset_server.load(FONT),
+                        font_size: 40.0,
+                        color: colors::TITLE_COLOR,
+                    },
+                )
+                .with_text_alignment(TextAlignment::Center),
+                LoadingLabel,
+            ));
+        });
+}
+
+/// Swaps in the settings of the session picked for replay, so that the loading and the session
+/// go by them, and turns the replay off in the stored settings so that only this session replays
+/// it.
+pub fn setup_replay(
+    mut commands: Commands,
+    mut errors: ResMut<LoadingErrors>,
+    mut settings: ResMut<SettingValues>,
+    mut pkv: ResMut<PkvStore>,
+) {
+    let Some(id) = settings.replay_session.take() else {
+        return;
+    };
+    pkv.set("settingValues", &*settings)
+        .expect("failed to store settings");
+
+    match pkv.get::<SessionLog>(&SessionLog::key(id)) {
+        Ok(SessionLog {
+            config: Some(config),
+            settings: Some(replayed),
+            ..
+        }) => {
+            info!("Replaying session {}", id);
+            *settings = replayed;
+            commands.insert_resource(Replay(config));
+        }
+        Ok(_) => errors.0.push(format!(
+            "Session {} was saved by an older version and can't be replayed",
+            id
+        )),
+        Err(_) => errors.0.push(format!("Session {} could not be found", id)),
+    }
+}
+
+/// Puts the player's own settings back once a replay is over.
+pub fn end_replay(
+    mut commands: Commands,
+    replay: Option<Res<Replay>>,
+    mut settings: ResMut<SettingValues>,
+    pkv: Res<PkvStore>,
+) {
+    if replay.is_none() {
+        return;
+    }
+    commands.remove_resource::<Replay>();
+    if let Ok(stored) = pkv.get::<SettingValues>("settingValues") {
+        *settings = stored;
+    } else {
+        error!("Failed to load settings");
+    }
+}
+
+/// Builds the layout picked in the settings. Every session draws its board, so a custom layout
+/// that fails to load stops the session, and the grid stands in until the error is shown.
+pub fn setup_layout(
+    mut commands: Commands,
+    mut errors: ResMut<LoadingErrors>,
+    settings: Res<SettingValues>,
+) {
+    let layout = Layout::from_settings(&settings).unwrap_or_else(|message| {
+        errors.0.push(format!(
+            "Layout {} could not be loaded: {}",
+            settings.custom_layout, message
+        ));
+        Layout::grid(Grid::from_settings(&settings))
+    });
+    commands.insert_resource(layout);
+}
+
+pub fn setup_tones(
+    mut commands: Commands,
+    settings: Res<SettingValues>,
+    mut tones: ResMut<Assets<Tone>>,
+) {
+    commands.insert_resource(ToneSet::from_settings(&settings, &mut tones));
+}
+
+/// Loads the sound pack picked in the settings. When it fails validation, the sessions that
+/// speak or show its letters can't start, and an empty pack stands in until the error is shown.
+pub fn setup_sound_pack(
+    mut commands: Commands,
+    mut errors: ResMut<LoadingErrors>,
+    settings: Res<SettingValues>,
+) {
+    let sound_pack = SoundPack::load(&settings.sound_pack).unwrap_or_else(|message| {
+        let modalities = settings.modalities();
+        if modalities.contains(&ModalityKind::AUDIO)
+            || modalities.contains(&ModalityKind::VISUAL_LETTER)
+        {
+            errors.0.push(format!(
+                "Sound pack {} could not be loaded: {}",
+                settings.sound_pack, message
+            ));
+        }
+        SoundPack {
+            id: settings.sound_pack.clone(),
+            name: String::new(),
+            stimuli: Vec::new(),
+        }
+    });
+    info!("Sound pack: {}", sound_pack.name);
+    commands.insert_resource(sound_pack);
+}
+
+/// Loads the spoken digits. Only arithmetic plays them, so a broken pack only stops the
+/// sessions that use it.
+pub fn setup_digit_pack(
+    mut commands: Commands,
+    mut errors: ResMut<LoadingErrors>,
+    settings: Res<SettingValues>,
+) {
+    let digits = DigitPack::load().unwrap_or_else(|message| {
+        if settings.modalities().contains(&ModalityKind::ARITHMETIC) {
+            errors.0.push(format!(
+                "Sound pack {} could not be loaded: {}",
+                DIGIT_SOUND_PACK, message
+            ));
+        }
+        DigitPack(SoundPack {
+            id: DIGIT_SOUND_PACK.to_string(),
+            name: String::new(),
+            stimuli: Vec::new(),
+        })
+    });
+    commands.insert_resource(digits);
+}
+
+/// Starts loading whatever the modalities of the session may show or play, so no trial waits
+/// on the disk.
+pub fn setup_session_assets(
+    mut commands: Commands,
+    settings: Res<SettingValues>,
+    registry: Res<ModalityRegistry>,
+    sets: StimulusSets,
+    asset_server: Res<AssetServer>,
+) {
+    let modalities = settings
+        .modalities()
+        .into_iter()
+        .filter_map(|kind| registry.get(kind))
+        .collect::<Vec<_>>();
+    let assets = SessionAssets {
+        fonts: vec![asset_server.load(FONT)],
+        sounds: modalities
+            .iter()
+            .flat_map(|modality| modality.sounds(&settings, &sets, &asset_server))
+            .collect(),
+        images: modalities
+            .iter()
+            .flat_map(|modality| modality.images(&settings, &sets, &asset_server))
+            .collect(),
+    };
+    info!(
+        "Loading {} fonts, {} sounds and {} images",
+        assets.fonts.len(),
+        assets.sounds.len(),
+        assets.images.len()
+    );
+    commands.insert_resource(assets);
+}
+
+/// Starts the session once every asset has loaded and every recording decodes, or lists what
+/// went wrong.
+pub fn loading_system(
+    mut label_query: Query<&mut Text, With<LoadingLabel>>,
+    mut app_state: ResMut<NextState<AppState>>,
+    mut errors: ResMut<LoadingErrors>,
+    mut checked: Local<bool>,
+    assets: Res<SessionAssets>,
+    asset_server: Res<AssetServer>,
+    audio_sources: Res<Assets<AudioSource>>,
+) {
+    if assets.is_changed() {
+        *checked = false;
+    }
+    if *checked {
+        return;
+    }
+
+    let path = |handle: HandleId| {
+        asset_server.get_handle_path(handle).map_or_else(
+            || format!("{:?}", handle),
+            |path| path.path().display().to_string(),
+        )
+    };
+
+    let mut failed = Vec::new();
+    for handle in assets.handles() {
+        match asset_server.get_load_state(handle) {
+            LoadState::Loaded => {}
+            LoadState::Failed => failed.push(format!("{} is missing or unreadable", path(handle))),
+            _ => return,
+        }
+    }
+    errors.0.extend(failed);
+
+    // The recordings are only decoded when played, so a corrupt one is caught here rather than
+    // halfway through the session.
+    if errors.0.is_empty() {
+        for handle in &assets.sounds {
+            let Some(source) = audio_sources.get(handle) else {
+                continue;
+            };
+            if let Err(error) = rodio::Decoder::new(Cursor::new(source.clone())) {
+                errors
+                    .0
+                    .push(format!("{} can't be played: {}", path(handle.id()), error));
+            }
+        }
+    }
+
+    *checked = true;
+    if errors.0.is_empty() {
+        app_state.set(AppState::Session);
+        return;
+    }
+
+    for message in &errors.0 {
+        error!("{}", message);
+    }
+    for mut text in &mut label_query {
+        text.sections[0].value = format!(
+            "The session could not start:\n\n{}\n\nPress Esc to return to the menu",
+            errors.0.join("\n")
+        );
+        text.sections[0].style.font_size = 25.0;
+    }
+}
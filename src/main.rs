@@ -10,15 +10,31 @@ use winit::window::Icon;
 
 use colors::*;
 use database::*;
+use engine::*;
+use layout::*;
+use loading::*;
 use menu::*;
+use modality::*;
+use progression::*;
 use session::*;
 use settings::*;
+use sound_pack::*;
+use spatial::*;
+use tone::*;
 
 mod colors;
 mod database;
+mod engine;
+mod layout;
+mod loading;
 mod menu;
+mod modality;
+mod progression;
 mod session;
 mod settings;
+mod sound_pack;
+mod spatial;
+mod tone;
 
 pub fn set_window_icon(
     main_window: Query<Entity, With<PrimaryWindow>>,
@@ -45,6 +61,7 @@ pub fn set_window_icon(
 pub enum AppState {
     #[default]
     Menu,
+    Loading,
     Session,
     Settings,
     Progress,
@@ -78,6 +95,8 @@ fn main() {
         .add_systems(Startup, (setup_camera, set_window_icon))
         .add_plugins(DatabasePlugin)
         .add_plugins(MenuPlugin)
+        .add_plugins(ModalityPlugin)
+        .add_plugins(LoadingPlugin)
         .add_plugins(SessionPlugin)
         .add_plugins(SettingsPlugin)
         .run();
@@ -1,6 +1,9 @@
 use bevy::{prelude::*, window::WindowResized};
 
-use crate::{colors, despawn_screen, AppState, PkvStore, RecentSessions, StatValues};
+use crate::{
+    colors, despawn_screen, round_float, AppState, PkvStore, RecentSessions, Session,
+    SettingValues, StatValues,
+};
 
 pub struct MenuPlugin;
 
@@ -38,9 +41,6 @@ pub struct MinWindowWidth;
 #[derive(Component)]
 pub struct OnMenuScreen;
 
-#[derive(Component)]
-pub struct OnSettingsScreen;
-
 #[derive(Component)]
 pub enum MenuButtonAction {
     Start,
@@ -62,6 +62,7 @@ pub fn window_resize_system(
     }
 }
 
+#[allow(clippy::type_complexity)]
 pub fn menu_button_system(
     mut interaction_query: Query<
         (&Interaction, &mut BackgroundColor),
@@ -99,7 +100,7 @@ fn spawn_menu_button(builder: &mut ChildBuilder, text: &str, menu_button_action:
                 text,
                 TextStyle {
                     font_size: 25.0,
-                    color: Color::rgb(1.0, 1.0, 1.0).into(),
+                    color: Color::rgb(1.0, 1.0, 1.0),
                     ..default()
                 },
             ));
@@ -119,7 +120,7 @@ fn spawn_label(builder: &mut ChildBuilder, text: &str, value: String) {
 
 fn spawn_spacer(builder: &mut ChildBuilder) {
     builder.spawn(TextBundle::from_section(
-        format!(" "),
+        " ",
         TextStyle {
             font_size: 20.0,
             ..default()
@@ -129,6 +130,19 @@ fn spawn_spacer(builder: &mut ChildBuilder) {
 #[derive(Component)]
 pub struct SessionsBoard;
 
+/// Mean reaction times of a session's modalities, e.g. "P 512 / A 634 ms".
+fn format_reaction_ms(session: &Session) -> String {
+    if session.reaction_ms.is_empty() {
+        return "- ms".to_string();
+    }
+    let reaction_ms = session
+        .reaction_ms
+        .iter()
+        .map(|(modality, reaction_ms)| format!("{} {}", modality.abbreviation(), reaction_ms))
+        .collect::<Vec<_>>();
+    format!("{} ms", reaction_ms.join(" / "))
+}
+
 fn setup_sessionboard(mut commands: Commands, asset_server: Res<AssetServer>, pkv: Res<PkvStore>) {
     commands
         .spawn((
@@ -171,8 +185,12 @@ fn setup_sessionboard(mut commands: Commands, asset_server: Res<AssetServer>, pk
             for session in recent_sessions.sessions.iter().rev() {
                 spawn_label(
                     builder,
-                    &session.date.to_string(),
-                    format!("{}%", session.percent_score.to_string()),
+                    &format!("#{} {}", session.id, session.date),
+                    format!(
+                        "{}%, {}",
+                        session.percent_score,
+                        format_reaction_ms(session)
+                    ),
                 );
             }
         });
@@ -185,6 +203,7 @@ fn setup_scoreboard(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     stats: Res<StatValues>,
+    settings: Res<SettingValues>,
 ) {
     commands
         .spawn((
@@ -224,12 +243,21 @@ fn setup_scoreboard(
                 }),
             );
 
-            spawn_label(builder, "Current DNB", stats.current_level.to_string());
+            let mode = settings.game_mode;
+            spawn_label(
+                builder,
+                &format!("Current {}", mode.abbreviation()),
+                stats.level(mode).to_string(),
+            );
 
+            let average_level = stats
+                .levels_today
+                .get(&mode)
+                .map_or(0.0, |levels| levels.average_level);
             spawn_label(
                 builder,
-                "Average DNB",
-                stats.average_level_today.to_string(),
+                &format!("Average {}", mode.abbreviation()),
+                round_float(average_level).to_string(),
             );
             spawn_label(builder, "Sessions Today", stats.sessions_today.to_string());
 
@@ -289,6 +317,7 @@ pub fn setup_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
         });
 }
 
+#[allow(clippy::type_complexity)]
 fn menu_action(
     interaction_query: Query<
         (&Interaction, &MenuButtonAction),
@@ -302,7 +331,7 @@ fn menu_action(
             match menu_button_action {
                 MenuButtonAction::Start => {
                     menu_state.set(MenuState::Disabled);
-                    next_app_state.set(AppState::Session);
+                    next_app_state.set(AppState::Loading);
                 }
                 MenuButtonAction::Settings => {
                     next_app_state.set(AppState::Settings);
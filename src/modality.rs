@@ -0,0 +1,624 @@
+use crate::{
+    colors, panned_sound_system, DigitPack, Layout, ModalityKind, PannedAudio, SettingValues,
+    SoundPack, StagedSettingValues, TargetShape, Tone, ToneKind, ToneSet, Trial,
+};
+use bevy::{audio::AddAudioSource, ecs::system::SystemParam, prelude::*};
+use bevy_egui::egui;
+use std::collections::{BTreeMap, BTreeSet};
+use strum::IntoEnumIterator;
+
+/// Registers the built-in modalities.
+pub struct ModalityPlugin;
+
+impl Plugin for ModalityPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_audio_source::<Tone>()
+            .add_audio_source::<PannedAudio>()
+            .add_systems(Update, panned_sound_system)
+            .register_modality(PositionModality(ModalityKind::POSITION))
+            .register_modality(PositionModality(ModalityKind::POSITION2))
+            .register_modality(PositionModality(ModalityKind::POSITION3))
+            .register_modality(PositionModality(ModalityKind::POSITION4))
+            .register_modality(AudioModality)
+            .register_modality(ColorModality)
+            .register_modality(ShapeModality)
+            .register_modality(ArithmeticModality)
+            .register_modality(VisualLetterModality)
+            .register_modality(CrossModality(ModalityKind::VISUAL_AUDIO))
+            .register_modality(CrossModality(ModalityKind::AUDIO_VISUAL))
+            .register_modality(ToneModality)
+            .register_modality(SpatialModality);
+    }
+}
+
+/// A stimulus channel the session can present and score: its set size, match key, targets,
+/// lures, settings, assets, presentation and heads-up display. A new channel picks a fresh
+/// [`ModalityKind::new`] id and registers its modality from its own plugin. The engine compares
+/// every channel with its own value N back; comparisons across channels and typed answers are
+/// only known to it for the built-in ones (see [`ModalityKind::compares`]).
+pub trait Modality: Send + Sync {
+    fn kind(&self) -> ModalityKind;
+
+    fn label(&self) -> &'static str {
+        self.kind().label()
+    }
+
+    /// Keyboard shortcut of the match button, or `None` when the modality is answered some
+    /// other way and gets no button.
+    fn key(&self) -> Option<KeyCode>;
+
+    /// How many distinct stimuli the modality draws from.
+    fn size(&self, settings: &SettingValues, sets: &StimulusSets) -> u32;
+
+    /// Targets planned per session by exact count generation until the settings give others.
+    fn default_targets(&self) -> u32 {
+        4
+    }
+
+    /// Targets planned per session by exact count generation.
+    fn targets(&self, settings: &SettingValues) -> u32 {
+        settings
+            .modality_targets
+            .get(&self.kind())
+            .copied()
+            .unwrap_or_else(|| self.default_targets())
+    }
+
+    /// How often a non-matching stimulus repeats the one from N-1 or N+1 trials back.
+    fn lure_rate(&self, settings: &SettingValues) -> f32 {
+        settings
+            .lure_rates
+            .get(&self.kind())
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    /// Whether the modality can be ticked in custom mode alongside the `chosen` ones.
+    fn available_with(&self, _chosen: &BTreeSet<ModalityKind>) -> bool {
+        true
+    }
+
+    /// Settings of the modality besides its targets and lure rate, shown while it is played.
+    fn settings_ui(&self, _ui: &mut egui::Ui, _settings: &mut StagedSettingValues) {}
+
+    /// Color of the match button label, given every modality of the session.
+    fn button_color(&self, _session: &[ModalityKind]) -> Color {
+        colors::PRIMARY_COLOR
+    }
+
+    /// Lines the modality adds to the session's heads-up display, for the trial on screen, if
+    /// any, and the answer typed so far.
+    fn hud(&self, _trial: Option<&Trial>, _typed: &str) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Adds a pressed key to the typed answer, for modalities answered by typing.
+    fn type_key(&self, _key: KeyCode, _typed: &mut String) {}
+
+    /// Recordings the modality may play, loaded before the session starts so that no trial
+    /// waits on the disk.
+    fn sounds(
+        &self,
+        _settings: &SettingValues,
+        _sets: &StimulusSets,
+        _asset_server: &AssetServer,
+    ) -> Vec<Handle<AudioSource>> {
+        Vec::new()
+    }
+
+    /// Images the modality may show, loaded before the session starts like its sounds.
+    fn images(
+        &self,
+        _settings: &SettingValues,
+        _sets: &StimulusSets,
+        _asset_server: &AssetServer,
+    ) -> Vec<Handle<Image>> {
+        Vec::new()
+    }
+
+    /// Adds stimulus `index` of this modality to what the trial shows and plays.
+    fn present(
+        &self,
+        _index: u32,
+        _settings: &SettingValues,
+        _sets: &StimulusSets,
+        _presentation: &mut Presentation,
+        _asset_server: &AssetServer,
+    ) {
+    }
+
+    /// Spawns what stimulus `index` shows or plays that [`Presentation`] has no field for. The
+    /// entities are given [`TrialStimulus`] so that they are despawned when the next trial
+    /// begins.
+    fn spawn(
+        &self,
+        _index: u32,
+        _settings: &SettingValues,
+        _sets: &StimulusSets,
+        _commands: &mut Commands,
+        _asset_server: &AssetServer,
+    ) {
+    }
+}
+
+/// Marks what a modality spawned for the trial on screen; see [`Modality::spawn`].
+#[derive(Component)]
+pub struct TrialStimulus;
+
+/// What the stimuli of the session are picked from, besides the settings.
+#[derive(SystemParam)]
+pub struct StimulusSets<'w> {
+    pub layout: Res<'w, Layout>,
+    pub sound_pack: Res<'w, SoundPack>,
+    pub digits: Res<'w, DigitPack>,
+    pub tones: Res<'w, ToneSet>,
+}
+
+/// What a trial shows and plays, filled in by each of its modalities. The session draws each
+/// field its own way, so there is one per kind of output rather than one per modality. Other
+/// output is spawned by the modality itself with [`Modality::spawn`].
+#[derive(Default)]
+pub struct Presentation {
+    /// Cells lit this trial, with the position stream that lit each.
+    pub cells: Vec<(u32, usize)>,
+    pub color: Option<Color>,
+    pub image: Option<Handle<Image>>,
+    pub letter: Option<String>,
+    pub sounds: Vec<Handle<AudioSource>>,
+    pub tones: Vec<Handle<Tone>>,
+    /// Where between the speakers the sounds come from, from -1 for far left to 1 for far
+    /// right, or `None` to play them as recorded.
+    pub pan: Option<f32>,
+}
+
+/// The modality behind every [`ModalityKind`] the session can play. Registering another one for
+/// a kind with [`RegisterModality::register_modality`] replaces the one registered before.
+#[derive(Default, Resource)]
+pub struct ModalityRegistry(BTreeMap<ModalityKind, Box<dyn Modality>>);
+
+impl ModalityRegistry {
+    pub fn get(&self, kind: ModalityKind) -> Option<&dyn Modality> {
+        self.0.get(&kind).map(|modality| modality.as_ref())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Modality> {
+        self.0.values().map(|modality| modality.as_ref())
+    }
+}
+
+pub trait RegisterModality {
+    /// Adds `modality` to the [`ModalityRegistry`], replacing any registered for its kind.
+    fn register_modality(&mut self, modality: impl Modality + 'static) -> &mut Self;
+}
+
+impl RegisterModality for App {
+    fn register_modality(&mut self, modality: impl Modality + 'static) -> &mut Self {
+        self.init_resource::<ModalityRegistry>();
+        self.world
+            .resource_mut::<ModalityRegistry>()
+            .0
+            .insert(modality.kind(), Box::new(modality));
+        self
+    }
+}
+
+/// One of the squares lit on the grid.
+pub struct PositionModality(pub ModalityKind);
+
+impl Modality for PositionModality {
+    fn kind(&self) -> ModalityKind {
+        self.0
+    }
+
+    fn key(&self) -> Option<KeyCode> {
+        match self.0.position_stream()? {
+            0 => Some(KeyCode::A),
+            1 => Some(KeyCode::S),
+            2 => Some(KeyCode::D),
+            _ => Some(KeyCode::F),
+        }
+    }
+
+    fn size(&self, _settings: &SettingValues, sets: &StimulusSets) -> u32 {
+        sets.layout.cell_count()
+    }
+
+    /// Several position streams are told apart by color, and so are their buttons.
+    fn button_color(&self, session: &[ModalityKind]) -> Color {
+        let streams = session.iter().filter(|kind| kind.is_position()).count();
+        match self.0.position_stream() {
+            Some(stream) if streams > 1 => colors::POSITION_STREAM_COLORS[stream],
+            _ => colors::PRIMARY_COLOR,
+        }
+    }
+
+    fn present(
+        &self,
+        index: u32,
+        _settings: &SettingValues,
+        _sets: &StimulusSets,
+        presentation: &mut Presentation,
+        _asset_server: &AssetServer,
+    ) {
+        if let Some(stream) = self.0.position_stream() {
+            presentation.cells.push((index, stream));
+        }
+    }
+}
+
+/// A spoken stimulus from the sound pack, letters by default.
+pub struct AudioModality;
+
+impl Modality for AudioModality {
+    fn kind(&self) -> ModalityKind {
+        ModalityKind::AUDIO
+    }
+
+    fn key(&self) -> Option<KeyCode> {
+        Some(KeyCode::L)
+    }
+
+    fn size(&self, _settings: &SettingValues, sets: &StimulusSets) -> u32 {
+        sets.sound_pack.size()
+    }
+
+    /// Arithmetic speaks too, so only one of them can be on, and spatial audio pans the spoken
+    /// letters, so audio stays on with it.
+    fn available_with(&self, chosen: &BTreeSet<ModalityKind>) -> bool {
+        !chosen.contains(&ModalityKind::ARITHMETIC) && !chosen.contains(&ModalityKind::SPATIAL)
+    }
+
+    fn sounds(
+        &self,
+        _settings: &SettingValues,
+        sets: &StimulusSets,
+        asset_server: &AssetServer,
+    ) -> Vec<Handle<AudioSource>> {
+        sets.sound_pack
+            .stimuli
+            .iter()
+            .map(|stimulus| asset_server.load(&stimulus.file))
+            .collect()
+    }
+
+    fn present(
+        &self,
+        index: u32,
+        _settings: &SettingValues,
+        sets: &StimulusSets,
+        presentation: &mut Presentation,
+        asset_server: &AssetServer,
+    ) {
+        let Some(stimulus) = sets.sound_pack.stimuli.get(index as usize) else {
+            return;
+        };
+        debug!("Playing sound: {}", stimulus.label);
+
+        presentation.sounds.push(asset_server.load(&stimulus.file));
+    }
+}
+
+/// The color of the lit square.
+pub struct ColorModality;
+
+impl Modality for ColorModality {
+    fn kind(&self) -> ModalityKind {
+        ModalityKind::COLOR
+    }
+
+    fn key(&self) -> Option<KeyCode> {
+        Some(KeyCode::F)
+    }
+
+    fn size(&self, _settings: &SettingValues, _sets: &StimulusSets) -> u32 {
+        colors::STIMULUS_COLORS.len() as u32
+    }
+
+    fn present(
+        &self,
+        index: u32,
+        _settings: &SettingValues,
+        _sets: &StimulusSets,
+        presentation: &mut Presentation,
+        _asset_server: &AssetServer,
+    ) {
+        presentation.color = colors::STIMULUS_COLORS.get(index as usize).copied();
+    }
+}
+
+/// The shape drawn in place of the lit square.
+pub struct ShapeModality;
+
+impl Modality for ShapeModality {
+    fn kind(&self) -> ModalityKind {
+        ModalityKind::SHAPE
+    }
+
+    fn key(&self) -> Option<KeyCode> {
+        Some(KeyCode::J)
+    }
+
+    fn size(&self, _settings: &SettingValues, _sets: &StimulusSets) -> u32 {
+        TargetShape::iter().count() as u32
+    }
+
+    fn images(
+        &self,
+        _settings: &SettingValues,
+        _sets: &StimulusSets,
+        asset_server: &AssetServer,
+    ) -> Vec<Handle<Image>> {
+        TargetShape::iter()
+            .map(|shape| asset_server.load(shape_file(shape)))
+            .collect()
+    }
+
+    fn present(
+        &self,
+        index: u32,
+        _settings: &SettingValues,
+        _sets: &StimulusSets,
+        presentation: &mut Presentation,
+        asset_server: &AssetServer,
+    ) {
+        let Some(shape) = TargetShape::iter().nth(index as usize) else {
+            return;
+        };
+
+        presentation.image = Some(asset_server.load(shape_file(shape)));
+    }
+}
+
+fn shape_file(shape: TargetShape) -> &'static str {
+    match shape {
+        TargetShape::Circle => "shapes/circle.png",
+        TargetShape::Square => "shapes/square.png",
+        TargetShape::Triangle => "shapes/triangle.png",
+        TargetShape::Diamond => "shapes/diamond.png",
+        TargetShape::Cross => "shapes/cross.png",
+        TargetShape::Star => "shapes/star.png",
+        TargetShape::Hexagon => "shapes/hexagon.png",
+        TargetShape::Ring => "shapes/ring.png",
+    }
+}
+
+/// A spoken digit, combined with the digit N back into an answer that is typed in.
+pub struct ArithmeticModality;
+
+impl Modality for ArithmeticModality {
+    fn kind(&self) -> ModalityKind {
+        ModalityKind::ARITHMETIC
+    }
+
+    fn key(&self) -> Option<KeyCode> {
+        None
+    }
+
+    fn size(&self, _settings: &SettingValues, sets: &StimulusSets) -> u32 {
+        sets.digits.0.size()
+    }
+
+    /// Every trial past the first N is answered, so there are no targets to plan.
+    fn default_targets(&self) -> u32 {
+        0
+    }
+
+    fn available_with(&self, chosen: &BTreeSet<ModalityKind>) -> bool {
+        !chosen.contains(&ModalityKind::AUDIO)
+    }
+
+    fn hud(&self, trial: Option<&Trial>, typed: &str) -> Vec<String> {
+        let operation = trial
+            .and_then(|trial| trial.problem)
+            .map_or("-", |problem| problem.operation.symbol());
+        vec![
+            format!("Operation: {}", operation),
+            format!("Answer: {}", typed),
+        ]
+    }
+
+    fn type_key(&self, key: KeyCode, typed: &mut String) {
+        match key {
+            KeyCode::Back => {
+                typed.pop();
+            }
+            KeyCode::Minus | KeyCode::NumpadSubtract if typed.is_empty() => {
+                typed.push('-');
+            }
+            _ => {
+                if let Some(digit) = typed_digit(key) {
+                    if typed.len() < 3 {
+                        typed.push(digit);
+                    }
+                }
+            }
+        }
+    }
+
+    fn sounds(
+        &self,
+        _settings: &SettingValues,
+        sets: &StimulusSets,
+        asset_server: &AssetServer,
+    ) -> Vec<Handle<AudioSource>> {
+        sets.digits
+            .0
+            .stimuli
+            .iter()
+            .map(|stimulus| asset_server.load(&stimulus.file))
+            .collect()
+    }
+
+    fn present(
+        &self,
+        index: u32,
+        _settings: &SettingValues,
+        sets: &StimulusSets,
+        presentation: &mut Presentation,
+        asset_server: &AssetServer,
+    ) {
+        let Some(stimulus) = sets.digits.0.stimuli.get(index as usize) else {
+            return;
+        };
+        debug!("Playing digit: {}", stimulus.label);
+
+        presentation.sounds.push(asset_server.load(&stimulus.file));
+    }
+}
+
+fn typed_digit(key: KeyCode) -> Option<char> {
+    match key {
+        KeyCode::Key0 | KeyCode::Numpad0 => Some('0'),
+        KeyCode::Key1 | KeyCode::Numpad1 => Some('1'),
+        KeyCode::Key2 | KeyCode::Numpad2 => Some('2'),
+        KeyCode::Key3 | KeyCode::Numpad3 => Some('3'),
+        KeyCode::Key4 | KeyCode::Numpad4 => Some('4'),
+        KeyCode::Key5 | KeyCode::Numpad5 => Some('5'),
+        KeyCode::Key6 | KeyCode::Numpad6 => Some('6'),
+        KeyCode::Key7 | KeyCode::Numpad7 => Some('7'),
+        KeyCode::Key8 | KeyCode::Numpad8 => Some('8'),
+        KeyCode::Key9 | KeyCode::Numpad9 => Some('9'),
+        _ => None,
+    }
+}
+
+/// A letter written on the grid, from the same sound pack as the spoken letters.
+pub struct VisualLetterModality;
+
+impl Modality for VisualLetterModality {
+    fn kind(&self) -> ModalityKind {
+        ModalityKind::VISUAL_LETTER
+    }
+
+    fn key(&self) -> Option<KeyCode> {
+        Some(KeyCode::S)
+    }
+
+    fn size(&self, _settings: &SettingValues, sets: &StimulusSets) -> u32 {
+        sets.sound_pack.size()
+    }
+
+    fn present(
+        &self,
+        index: u32,
+        _settings: &SettingValues,
+        sets: &StimulusSets,
+        presentation: &mut Presentation,
+        _asset_server: &AssetServer,
+    ) {
+        presentation.letter = sets
+            .sound_pack
+            .stimuli
+            .get(index as usize)
+            .map(|stimulus| stimulus.label.clone());
+    }
+}
+
+/// A comparison between the visual and spoken letters. It presents nothing of its own.
+pub struct CrossModality(pub ModalityKind);
+
+impl Modality for CrossModality {
+    fn kind(&self) -> ModalityKind {
+        self.0
+    }
+
+    fn key(&self) -> Option<KeyCode> {
+        match self.0 {
+            ModalityKind::VISUAL_AUDIO => Some(KeyCode::D),
+            _ => Some(KeyCode::K),
+        }
+    }
+
+    fn size(&self, _settings: &SettingValues, _sets: &StimulusSets) -> u32 {
+        0
+    }
+
+    fn default_targets(&self) -> u32 {
+        2
+    }
+}
+
+/// A synthesized tone or chord, so there is a sound stream that can't be rehearsed as words.
+pub struct ToneModality;
+
+impl Modality for ToneModality {
+    fn kind(&self) -> ModalityKind {
+        ModalityKind::TONE
+    }
+
+    fn key(&self) -> Option<KeyCode> {
+        Some(KeyCode::H)
+    }
+
+    fn size(&self, _settings: &SettingValues, sets: &StimulusSets) -> u32 {
+        sets.tones.size()
+    }
+
+    fn settings_ui(&self, ui: &mut egui::Ui, settings: &mut StagedSettingValues) {
+        let tone_kind = &mut settings.tone_kind;
+        ui.horizontal(|ui| {
+            ui.label("Tones:");
+            ui.selectable_value(tone_kind, ToneKind::Pitch, "Pitches");
+            ui.selectable_value(tone_kind, ToneKind::Chord, "Chords");
+        });
+
+        let tone_count = &mut settings.tone_count;
+        ui.add(egui::Slider::new(tone_count, 2..=8).text("Tone Count"));
+
+        let tone_base_frequency = &mut settings.tone_base_frequency;
+        ui.add(egui::Slider::new(tone_base_frequency, 110.0..=880.0).text("Lowest Tone (Hz)"));
+    }
+
+    fn present(
+        &self,
+        index: u32,
+        _settings: &SettingValues,
+        sets: &StimulusSets,
+        presentation: &mut Presentation,
+        _asset_server: &AssetServer,
+    ) {
+        if let Some(tone) = sets.tones.0.get(index as usize) {
+            presentation.tones.push(tone.clone());
+        }
+    }
+}
+
+/// The stereo position the spoken letter is played from, matched apart from the letter itself.
+pub struct SpatialModality;
+
+impl Modality for SpatialModality {
+    fn kind(&self) -> ModalityKind {
+        ModalityKind::SPATIAL
+    }
+
+    fn key(&self) -> Option<KeyCode> {
+        Some(KeyCode::G)
+    }
+
+    fn size(&self, settings: &SettingValues, _sets: &StimulusSets) -> u32 {
+        settings.pan_positions.max(2)
+    }
+
+    /// It pans the spoken letters, so it needs audio on.
+    fn available_with(&self, chosen: &BTreeSet<ModalityKind>) -> bool {
+        chosen.contains(&ModalityKind::AUDIO)
+    }
+
+    fn settings_ui(&self, ui: &mut egui::Ui, settings: &mut StagedSettingValues) {
+        let pan_positions = &mut settings.pan_positions;
+        ui.add(egui::Slider::new(pan_positions, 2..=7).text("Stereo Positions"));
+    }
+
+    fn present(
+        &self,
+        index: u32,
+        settings: &SettingValues,
+        sets: &StimulusSets,
+        presentation: &mut Presentation,
+        _asset_server: &AssetServer,
+    ) {
+        // Positions are spread evenly from far left to far right.
+        let last = self.size(settings, sets) - 1;
+        presentation.pan = Some(2.0 * index as f32 / last as f32 - 1.0);
+    }
+}
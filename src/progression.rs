@@ -0,0 +1,321 @@
+use crate::{LevelMetric, LevelPolicyKind, Score, SettingValues};
+use serde::{Deserialize, Serialize};
+
+/// Decides the n-back level of the next session from how the last one went.
+pub trait LevelPolicy {
+    /// Returns the level to play next, given the level just played and its score. Policies that
+    /// need memory across sessions keep it in `state`, which is persisted with the stats.
+    fn next_level(&self, level: u32, score: &Score, state: &mut PolicyState) -> u32;
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicyState {
+    /// Consecutive failing sessions at the current level, for [`BrainWorkshopPolicy`].
+    pub strikes: u32,
+    /// Fractional level tracked by [`StaircasePolicy`].
+    pub staircase_level: f32,
+}
+
+impl LevelPolicyKind {
+    pub fn build(self, settings: &SettingValues) -> Box<dyn LevelPolicy> {
+        match self {
+            LevelPolicyKind::Threshold => Box::new(ThresholdPolicy {
+                metric: settings.level_metric,
+                raise_threshold: settings.raise_threshold,
+                lower_threshold: settings.lower_threshold,
+                raise_d_prime: settings.raise_d_prime,
+                lower_d_prime: settings.lower_d_prime,
+            }),
+            LevelPolicyKind::Jaeggi => Box::new(JaeggiPolicy),
+            LevelPolicyKind::BrainWorkshop => Box::new(BrainWorkshopPolicy),
+            LevelPolicyKind::Staircase => Box::new(StaircasePolicy {
+                criterion: settings.staircase_criterion,
+                up_step: settings.staircase_up_step,
+                down_step: settings.staircase_down_step,
+            }),
+        }
+    }
+}
+
+/// Raises or lowers the level by one when the chosen metric crosses a threshold.
+pub struct ThresholdPolicy {
+    pub metric: LevelMetric,
+    pub raise_threshold: f32,
+    pub lower_threshold: f32,
+    pub raise_d_prime: f32,
+    pub lower_d_prime: f32,
+}
+
+impl LevelPolicy for ThresholdPolicy {
+    fn next_level(&self, level: u32, score: &Score, _state: &mut PolicyState) -> u32 {
+        let (raise, lower) = match self.metric {
+            LevelMetric::PercentCorrect => {
+                let percent_score = score.percent_correct();
+                (
+                    percent_score > (self.raise_threshold * 100.0),
+                    percent_score < (self.lower_threshold * 100.0),
+                )
+            }
+            LevelMetric::DPrime => {
+                // Arithmetic is only ever right or wrong, and exact counts can leave a modality
+                // without targets, so modalities without a d′ are left out.
+                let d_prime = score
+                    .modalities
+                    .values()
+                    .filter_map(|modality| modality.detection())
+                    .map(|stats| stats.d_prime)
+                    .reduce(f32::min);
+                match d_prime {
+                    Some(d_prime) => (d_prime > self.raise_d_prime, d_prime < self.lower_d_prime),
+                    None => (false, false),
+                }
+            }
+        };
+
+        if raise {
+            level + 1
+        } else if lower {
+            lower_level(level)
+        } else {
+            level
+        }
+    }
+}
+
+/// Jaeggi et al. (2008): up after fewer than 3 errors in every modality, down after more than 5
+/// errors in any modality.
+pub struct JaeggiPolicy;
+
+impl LevelPolicy for JaeggiPolicy {
+    fn next_level(&self, level: u32, score: &Score, _state: &mut PolicyState) -> u32 {
+        let errors = score
+            .modalities
+            .values()
+            .map(|modality| modality.false_positive + modality.false_negative)
+            .collect::<Vec<_>>();
+
+        if errors.iter().all(|errors| *errors < 3) {
+            level + 1
+        } else if errors.iter().any(|errors| *errors > 5) {
+            lower_level(level)
+        } else {
+            level
+        }
+    }
+}
+
+/// Brain Workshop: up at 80% or better, and a strike below 50%. Three strikes in a row at the same
+/// level drop it by one.
+pub struct BrainWorkshopPolicy;
+
+impl LevelPolicy for BrainWorkshopPolicy {
+    fn next_level(&self, level: u32, score: &Score, state: &mut PolicyState) -> u32 {
+        let percent_score = score.percent_correct();
+
+        if percent_score >= 80.0 {
+            state.strikes = 0;
+            return level + 1;
+        }
+
+        if percent_score < 50.0 {
+            state.strikes += 1;
+            if state.strikes >= 3 {
+                state.strikes = 0;
+                return lower_level(level);
+            }
+        }
+
+        level
+    }
+}
+
+/// Weighted up/down staircase on a fractional level. A session with at least `criterion` (0.0 to
+/// 1.0) of its responses correct moves the level up by `up_step`, anything else moves it down by
+/// `down_step`. The ratio of the steps sets the success rate the staircase settles at:
+/// `down_step / (up_step + down_step)`.
+pub struct StaircasePolicy {
+    pub criterion: f32,
+    pub up_step: f32,
+    pub down_step: f32,
+}
+
+impl LevelPolicy for StaircasePolicy {
+    fn next_level(&self, level: u32, score: &Score, state: &mut PolicyState) -> u32 {
+        // Start over from the played level if it was changed outside the staircase.
+        if state.staircase_level.round() as u32 != level {
+            state.staircase_level = level as f32;
+        }
+
+        if score.percent_correct() >= self.criterion * 100.0 {
+            state.staircase_level += self.up_step;
+        } else {
+            state.staircase_level = (state.staircase_level - self.down_step).max(1.0);
+        }
+
+        state.staircase_level.round() as u32
+    }
+}
+
+fn lower_level(level: u32) -> u32 {
+    level.saturating_sub(1).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ModalityKind, ModalityScore};
+
+    /// A session with `correct` hits and `wrong` false alarms in each of position and audio, on
+    /// top of 10 correct rejections each.
+    fn score(correct: u32, wrong: u32) -> Score {
+        let modality = ModalityScore {
+            correct,
+            false_positive: wrong,
+            correct_rejection: 10,
+            ..Default::default()
+        };
+        Score {
+            modalities: [ModalityKind::POSITION, ModalityKind::AUDIO]
+                .into_iter()
+                .map(|kind| (kind, modality))
+                .collect(),
+        }
+    }
+
+    fn threshold(metric: LevelMetric) -> ThresholdPolicy {
+        ThresholdPolicy {
+            metric,
+            raise_threshold: 0.8,
+            lower_threshold: 0.5,
+            raise_d_prime: 2.5,
+            lower_d_prime: 1.0,
+        }
+    }
+
+    #[test]
+    fn threshold_policy_follows_percent_correct() {
+        let policy = threshold(LevelMetric::PercentCorrect);
+        let state = &mut PolicyState::default();
+        assert_eq!(policy.next_level(3, &score(9, 1), state), 4);
+        assert_eq!(policy.next_level(3, &score(6, 4), state), 3);
+        assert_eq!(policy.next_level(3, &score(4, 6), state), 2);
+        assert_eq!(policy.next_level(1, &score(4, 6), state), 1);
+    }
+
+    #[test]
+    fn threshold_policy_follows_the_lowest_d_prime() {
+        let policy = threshold(LevelMetric::DPrime);
+        let state = &mut PolicyState::default();
+        // 10 hits and a miss against no false alarms is a d′ near 3.
+        let mut sharp = score(10, 0);
+        for modality in sharp.modalities.values_mut() {
+            modality.false_negative = 1;
+        }
+        assert_eq!(policy.next_level(3, &sharp, state), 4);
+
+        // One modality guessing holds back the other.
+        let guessing = ModalityScore {
+            correct: 5,
+            false_negative: 5,
+            false_positive: 5,
+            correct_rejection: 5,
+        };
+        sharp.modalities.insert(ModalityKind::AUDIO, guessing);
+        assert_eq!(policy.next_level(3, &sharp, state), 2);
+
+        // Without non-targets in any modality there is no d′, and the level stays.
+        let mut no_non_targets = score(10, 0);
+        for modality in no_non_targets.modalities.values_mut() {
+            modality.correct_rejection = 0;
+        }
+        assert_eq!(policy.next_level(3, &no_non_targets, state), 3);
+    }
+
+    #[test]
+    fn jaeggi_policy_counts_errors_per_modality() {
+        let state = &mut PolicyState::default();
+        assert_eq!(JaeggiPolicy.next_level(3, &score(10, 2), state), 4);
+        assert_eq!(JaeggiPolicy.next_level(3, &score(10, 4), state), 3);
+
+        let mut one_bad = score(10, 2);
+        one_bad.modalities.insert(
+            ModalityKind::AUDIO,
+            score(10, 6).modalities[&ModalityKind::AUDIO],
+        );
+        assert_eq!(JaeggiPolicy.next_level(3, &one_bad, state), 2);
+    }
+
+    #[test]
+    fn brain_workshop_policy_drops_after_three_strikes() {
+        let policy = BrainWorkshopPolicy;
+        let state = &mut PolicyState::default();
+        assert_eq!(policy.next_level(3, &score(8, 2), state), 4);
+
+        assert_eq!(policy.next_level(3, &score(4, 6), state), 3);
+        assert_eq!(policy.next_level(3, &score(6, 4), state), 3);
+        assert_eq!(policy.next_level(3, &score(4, 6), state), 3);
+        assert_eq!(state.strikes, 2);
+        assert_eq!(policy.next_level(3, &score(4, 6), state), 2);
+        assert_eq!(state.strikes, 0);
+
+        // Passing wipes the strikes.
+        policy.next_level(2, &score(4, 6), state);
+        policy.next_level(2, &score(9, 1), state);
+        assert_eq!(state.strikes, 0);
+    }
+
+    #[test]
+    fn staircase_policy_steps_a_fractional_level() {
+        let policy = StaircasePolicy {
+            criterion: 0.8,
+            up_step: 0.25,
+            down_step: 0.75,
+        };
+        let state = &mut PolicyState::default();
+        assert_eq!(policy.next_level(3, &score(9, 1), state), 3);
+        assert_eq!(state.staircase_level, 3.25);
+        assert_eq!(policy.next_level(3, &score(9, 1), state), 4);
+        assert_eq!(policy.next_level(4, &score(5, 5), state), 3);
+        assert_eq!(state.staircase_level, 2.75);
+
+        // A level set by hand restarts the staircase there.
+        assert_eq!(policy.next_level(6, &score(5, 5), state), 5);
+        assert_eq!(state.staircase_level, 5.25);
+
+        // It never goes below 1.
+        state.staircase_level = 1.0;
+        assert_eq!(policy.next_level(1, &score(0, 5), state), 1);
+        assert_eq!(state.staircase_level, 1.0);
+    }
+
+    #[test]
+    fn threshold_policy_leaves_out_modalities_without_a_d_prime() {
+        let policy = threshold(LevelMetric::DPrime);
+        let state = &mut PolicyState::default();
+        let mut sharp = score(10, 0);
+        for modality in sharp.modalities.values_mut() {
+            modality.false_negative = 1;
+        }
+
+        // Arithmetic only scores hits and misses.
+        let arithmetic = ModalityScore {
+            correct: 12,
+            false_negative: 8,
+            ..Default::default()
+        };
+        sharp
+            .modalities
+            .insert(ModalityKind::ARITHMETIC, arithmetic);
+        assert_eq!(policy.next_level(3, &sharp, state), 4);
+
+        // Nor does a modality given no targets.
+        let no_targets = ModalityScore {
+            false_positive: 6,
+            correct_rejection: 14,
+            ..Default::default()
+        };
+        sharp.modalities.insert(ModalityKind::COLOR, no_targets);
+        assert_eq!(policy.next_level(3, &sharp, state), 4);
+    }
+}
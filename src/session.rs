@@ -1,20 +1,19 @@
 use crate::{
-    colors, despawn_screen, AppState, CurrentDate, DayEntry, EntryValues, RecentSessions, Session,
-    SettingValues, StatValues,
+    colors, despawn_screen, response_categories, AppState, CurrentDate, DayEntry, EngineConfig,
+    EntryValues, GenerationMode, Generator, Layout, ModalityKind, ModalityRegistry, NBackEngine,
+    NBackVariant, PannedSound, Presentation, RecentSessions, Replay, Response, Session, SessionLog,
+    SettingValues, StatValues, StimulusSets, Trial, TrialRecord, TrialStimulus,
 };
-use bevy::{prelude::*, sprite::MaterialMesh2dBundle};
+use bevy::{ecs::query::ReadOnlyWorldQuery, prelude::*};
 use bevy_pkv::PkvStore;
-use rand::Rng;
-use std::iter;
-use strum::IntoEnumIterator;
-use strum_macros::EnumIter;
+use chrono::{DateTime, Local};
+use std::collections::{BTreeMap, HashMap};
 
 pub struct SessionPlugin;
 
 impl Plugin for SessionPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(TrialTimer(Timer::from_seconds(3.0, TimerMode::Repeating)))
-            .add_state::<SessionState>()
+        app.add_state::<SessionState>()
             .add_systems(
                 OnEnter(AppState::Session),
                 (
@@ -34,6 +33,7 @@ impl Plugin for SessionPlugin {
                     target_transition_system,
                     trial_progression_system,
                     trial_count_system,
+                    answer_input_system,
                 )
                     .run_if(in_state(AppState::Session)),
             )
@@ -50,6 +50,7 @@ impl Plugin for SessionPlugin {
 }
 
 pub const CELL_SIZE: f32 = 150.0;
+pub const TARGET_SIZE: f32 = 128.0;
 pub const VERTICAL_OFFSET: f32 = 75.0;
 pub const GRID_THICKNESS: f32 = 2.0;
 pub const GRID_LENGTH: f32 = 3.0 * CELL_SIZE;
@@ -61,251 +62,217 @@ pub enum SessionState {
     Active,
 }
 
-#[derive(EnumIter, Component, Debug, Copy, Clone, Eq, PartialEq, Reflect)]
-pub enum TargetAudio {
-    C,
-    H,
-    K,
-    L,
-    Q,
-    R,
-    S,
-    T,
-}
-
-impl TargetAudio {
-    fn random() -> Self {
-        let mut rng = rand::thread_rng();
-        match rng.gen_range(0..8) {
-            0 => TargetAudio::C,
-            1 => TargetAudio::H,
-            2 => TargetAudio::K,
-            3 => TargetAudio::L,
-            4 => TargetAudio::Q,
-            5 => TargetAudio::R,
-            6 => TargetAudio::S,
-            7 => TargetAudio::T,
-            _ => unreachable!(),
-        }
-    }
-}
+#[derive(Component)]
+pub struct OnSessionScreen;
 
-#[derive(EnumIter, Component, Debug, Copy, Clone, Eq, PartialEq, Reflect)]
-pub enum TargetLocation {
-    TopLeft,
-    TopMiddle,
-    TopRight,
-    CenterLeft,
-    CenterMiddle,
-    CenterRight,
-    BottomLeft,
-    BottomMiddle,
-    BottomRight,
-}
+#[derive(Debug, Resource)]
+pub struct TrialTimer(pub Timer);
 
-impl TargetLocation {
-    fn random() -> Self {
-        let mut rng = rand::thread_rng();
-        match rng.gen_range(0..9) {
-            0 => TargetLocation::TopLeft,
-            1 => TargetLocation::TopMiddle,
-            2 => TargetLocation::TopRight,
-            3 => TargetLocation::CenterLeft,
-            4 => TargetLocation::CenterMiddle,
-            5 => TargetLocation::CenterRight,
-            6 => TargetLocation::BottomLeft,
-            7 => TargetLocation::BottomMiddle,
-            8 => TargetLocation::BottomRight,
-            _ => unreachable!(),
-        }
-    }
-}
+#[derive(Debug, Resource)]
+pub struct SessionEngine(pub NBackEngine);
 
-pub fn get_target_coordinates(target_location: TargetLocation) -> (f32, f32) {
-    match target_location {
-        TargetLocation::TopLeft => (-1.0 * CELL_SIZE, CELL_SIZE),
-        TargetLocation::TopMiddle => (0.0, CELL_SIZE),
-        TargetLocation::TopRight => (CELL_SIZE, CELL_SIZE),
-        TargetLocation::CenterLeft => (-1.0 * CELL_SIZE, 0.0),
-        TargetLocation::CenterMiddle => (0.0, 0.0),
-        TargetLocation::CenterRight => (CELL_SIZE, 0.0),
-        TargetLocation::BottomLeft => (-1.0 * CELL_SIZE, -1.0 * CELL_SIZE),
-        TargetLocation::BottomMiddle => (0.0, -1.0 * CELL_SIZE),
-        TargetLocation::BottomRight => (CELL_SIZE, -1.0 * CELL_SIZE),
-    }
-}
+/// What the user has typed so far as the answer to the trial on screen, for the modalities
+/// answered by typing.
+#[derive(Debug, Default, Resource)]
+pub struct TypedAnswer(pub String);
 
 #[derive(Component)]
-pub struct OnSessionScreen;
+pub struct TargetCell(pub u32);
 
-#[derive(Debug, Resource)]
-pub struct TrialTimer(pub Timer);
+/// A sound played by a trial. It is despawned when it finishes or when the next trial starts.
+#[derive(Component)]
+pub struct StimulusSound;
 
 pub fn setup_session_state(mut session_state: ResMut<NextState<SessionState>>) {
     session_state.set(SessionState::Active);
 }
 
-pub fn setup_grid(mut commands: Commands) {
-    commands.spawn((
-        SpriteBundle {
-            sprite: Sprite {
-                color: colors::SECONDARY_COLOR,
-                custom_size: Some(Vec2::new(GRID_THICKNESS, GRID_LENGTH)),
-                ..Default::default()
-            },
-            transform: Transform::from_translation(Vec3::new(CELL_SIZE / 2.0, VERTICAL_OFFSET, 0.)),
-            ..Default::default()
-        },
-        OnSessionScreen,
-    ));
-    commands.spawn((
-        SpriteBundle {
-            sprite: Sprite {
-                color: colors::SECONDARY_COLOR,
-                custom_size: Some(Vec2::new(GRID_THICKNESS, GRID_LENGTH)),
+pub fn setup_grid(mut commands: Commands, layout: Res<Layout>) {
+    let Some(grid) = layout.grid else {
+        // Without a grid, each position gets an outline of its own.
+        for cell in &layout.cells {
+            let translation = cell.extend(0.0) + Vec3::Y * VERTICAL_OFFSET;
+            for (size, color, depth) in [
+                (layout.cell_size, colors::SECONDARY_COLOR, -2.0),
+                (
+                    layout.cell_size - 2.0 * GRID_THICKNESS,
+                    colors::BACKGROUND_COLOR,
+                    -1.0,
+                ),
+            ] {
+                commands.spawn((
+                    SpriteBundle {
+                        sprite: Sprite {
+                            color,
+                            custom_size: Some(Vec2::splat(size)),
+                            ..Default::default()
+                        },
+                        transform: Transform::from_translation(translation + Vec3::Z * depth),
+                        ..Default::default()
+                    },
+                    OnSessionScreen,
+                ));
+            }
+        }
+        return;
+    };
+
+    let cell_size = layout.cell_size;
+    let width = grid.columns as f32 * cell_size;
+    let height = grid.rows as f32 * cell_size;
+
+    for column in 1..grid.columns {
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: colors::SECONDARY_COLOR,
+                    custom_size: Some(Vec2::new(GRID_THICKNESS, height)),
+                    ..Default::default()
+                },
+                transform: Transform::from_translation(Vec3::new(
+                    column as f32 * cell_size - width / 2.0,
+                    VERTICAL_OFFSET,
+                    0.,
+                )),
                 ..Default::default()
             },
-            transform: Transform::from_translation(Vec3::new(
-                -1.0 * CELL_SIZE / 2.0,
-                VERTICAL_OFFSET,
-                0.,
-            )),
-            ..Default::default()
-        },
-        OnSessionScreen,
-    ));
-    commands.spawn((
-        SpriteBundle {
-            sprite: Sprite {
-                color: colors::SECONDARY_COLOR,
-                custom_size: Some(Vec2::new(GRID_LENGTH, GRID_THICKNESS)),
+            OnSessionScreen,
+        ));
+    }
+    for row in 1..grid.rows {
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: colors::SECONDARY_COLOR,
+                    custom_size: Some(Vec2::new(width, GRID_THICKNESS)),
+                    ..Default::default()
+                },
+                transform: Transform::from_translation(Vec3::new(
+                    0.0,
+                    height / 2.0 - row as f32 * cell_size + VERTICAL_OFFSET,
+                    0.,
+                )),
                 ..Default::default()
             },
-            transform: Transform::from_translation(Vec3::new(
-                0.0,
-                -1.0 * CELL_SIZE / 2.0 + VERTICAL_OFFSET,
-                0.,
-            )),
-            ..default()
-        },
-        OnSessionScreen,
-    ));
-    commands.spawn((
-        SpriteBundle {
-            sprite: Sprite {
-                color: colors::SECONDARY_COLOR,
-                custom_size: Some(Vec2::new(GRID_LENGTH, GRID_THICKNESS)),
-                ..default()
-            },
-            transform: Transform::from_translation(Vec3::new(
-                0.0,
-                CELL_SIZE / 2.0 + VERTICAL_OFFSET,
-                0.,
-            )),
-            ..default()
-        },
-        OnSessionScreen,
-    ));
+            OnSessionScreen,
+        ));
+    }
 }
 
 pub fn keyboard_input_system(
     app_state: Res<State<AppState>>,
     mut change_app_state: ResMut<NextState<AppState>>,
     keyboard_input: Res<Input<KeyCode>>,
-    mut stimuli_button_query: Query<(&mut MatchState, &StimuliButtonAction), With<StimuliButton>>,
+    registry: Res<ModalityRegistry>,
+    mut stimuli_button_query: Query<
+        (&mut MatchState, &mut RespondedAt, &StimuliButtonAction),
+        With<StimuliButton>,
+    >,
 ) {
     if keyboard_input.just_pressed(KeyCode::Escape) {
         if let AppState::Session = app_state.get() {
             change_app_state.set(AppState::Menu);
         } else {
-            change_app_state.set(AppState::Session);
-        }
-    } else if keyboard_input.just_pressed(KeyCode::A) {
-        for (mut match_state, stimuli_button_action) in &mut stimuli_button_query {
-            if StimuliButtonAction::MatchPosition == *stimuli_button_action
-                && *match_state != MatchState::Inactive
-            {
-                println!("Typed Position Match");
-                *match_state = MatchState::Match;
-            }
+            change_app_state.set(AppState::Loading);
         }
-    } else if keyboard_input.just_pressed(KeyCode::L) {
-        for (mut match_state, stimuli_button_action) in &mut stimuli_button_query {
-            if StimuliButtonAction::MatchAudio == *stimuli_button_action
+    } else {
+        for (mut match_state, mut responded_at, stimuli_button_action) in &mut stimuli_button_query
+        {
+            let Some(modality) = registry.get(stimuli_button_action.0) else {
+                continue;
+            };
+            if modality
+                .key()
+                .is_some_and(|key| keyboard_input.just_pressed(key))
                 && *match_state != MatchState::Inactive
             {
-                println!("Typed Audio Match");
-                *match_state = MatchState::Match;
+                println!("Typed {} Match", modality.label());
+                register_match(&mut match_state, &mut responded_at);
             }
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn exit_session_system(
     mut app_state: ResMut<NextState<AppState>>,
     mut pkv: ResMut<PkvStore>,
     mut stats: ResMut<StatValues>,
     current_date: Res<CurrentDate>,
-    score: ResMut<Score>,
+    engine: Res<SessionEngine>,
+    session_log: Res<SessionLog>,
     settings: ResMut<SettingValues>,
+    replay: Option<Res<Replay>>,
 ) {
-    let num_correct = score.position_correct + score.audio_correct;
-    let num_wrong = score.position_false_positive
-        + score.position_false_negative
-        + score.audio_false_positive
-        + score.audio_false_negative;
-    let percent_score = 100.0 * (num_correct) as f32 / (num_wrong + num_correct) as f32;
-
-    stats.average_level_today = round_float(
-        (stats.average_level_today * stats.sessions_today as f32 + stats.current_level as f32)
-            / (stats.sessions_today + 1) as f32,
-    );
+    let score = engine.0.score();
+    let percent_score = score.percent_correct();
+    let detection = score.detection();
+    info!("Detection {:?}", detection);
+
+    let mode = session_log.mode;
+    let level = session_log.level;
 
     stats.sessions_today += 1;
     stats.total_sessions += 1;
 
-    if percent_score > (settings.raise_threshold * 100.0) {
-        stats.current_level += 1;
-        println!("Level Up!");
-    } else if percent_score < settings.lower_threshold {
-        if stats.current_level > 1 {
-            stats.current_level -= 1;
+    // A replay is played at the level of the session it replays, so it leaves the level alone.
+    if replay.is_none() {
+        stats.levels_today.entry(mode).or_default().record(level);
+
+        let policy = settings.level_policy.build(&settings);
+        let next_level = policy.next_level(level, score, stats.level_state_mut(mode));
+
+        if next_level > level {
+            println!("Level Up!");
+        } else if next_level < level {
+            println!("Level Down!");
         }
-        println!("Level Down!");
-    }
+        *stats.level_mut(mode) = next_level;
+
+        if let Ok(entries) = pkv.get::<EntryValues>("entryValues") {
+            let mut new_entries = entries.clone();
+
+            let entry = new_entries
+                .day_entries
+                .entry(current_date.date)
+                .or_insert_with(|| DayEntry {
+                    date: current_date.date,
+                    average_level: 0.0,
+                    sessions_completed: 0,
+                    max_level: 1,
+                    modes: HashMap::new(),
+                });
 
-    if let Ok(entries) = pkv.get::<EntryValues>("entryValues") {
-        let mut new_entries = entries.clone();
-
-        let entry = new_entries
-            .day_entries
-            .entry(current_date.date)
-            .or_insert_with(|| DayEntry {
-                date: current_date.date,
-                average_level: 0.0,
-                sessions_completed: 0,
-                max_level: 1,
-            });
-
-        entry.max_level = std::cmp::max(stats.current_level, entry.max_level);
-        entry.average_level = (entry.average_level * entry.sessions_completed as f32
-            + stats.current_level as f32)
-            / (entry.sessions_completed + 1) as f32;
-        entry.sessions_completed += 1;
-
-        pkv.set("entryValues", &new_entries)
-            .expect("failed to store trials");
-    } else {
-        error!("Failed to load entry values");
+            entry.max_level = std::cmp::max(next_level, entry.max_level);
+            entry.average_level = (entry.average_level * entry.sessions_completed as f32
+                + next_level as f32)
+                / (entry.sessions_completed + 1) as f32;
+            entry.sessions_completed += 1;
+            entry.modes.entry(mode).or_default().record(next_level);
+
+            pkv.set("entryValues", &new_entries)
+                .expect("failed to store trials");
+        } else {
+            error!("Failed to load entry values");
+        }
     }
 
     if let Ok(recent_sessions) = pkv.get::<RecentSessions>("recentSessions") {
         let mut new_recent_sessions = recent_sessions.clone();
 
         new_recent_sessions.sessions.push_back(Session {
+            id: stats.total_sessions,
             date: current_date.date,
-            level: stats.current_level,
+            mode,
+            level,
             percent_score: percent_score as u32,
+            seed: engine.0.seed(),
+            reaction_ms: score
+                .modalities
+                .keys()
+                .filter_map(|modality| Some((*modality, session_log.mean_reaction_ms(*modality)?)))
+                .collect(),
+            detection,
         });
 
         if new_recent_sessions.sessions.len() > 10 {
@@ -318,9 +285,13 @@ pub fn exit_session_system(
         error!("Failed to load recent sessions");
     }
 
+    pkv.set(SessionLog::key(stats.total_sessions), &*session_log)
+        .expect("failed to store session log");
+
     app_state.set(AppState::Menu);
 }
 
+#[allow(clippy::type_complexity)]
 pub fn button_color_system(
     mut interaction_query: Query<
         (&Interaction, &mut BackgroundColor),
@@ -336,28 +307,31 @@ pub fn button_color_system(
     }
 }
 
+#[allow(clippy::type_complexity)]
 pub fn stimuli_button_action(
     mut interaction_query: Query<
-        (&Interaction, &mut MatchState),
+        (&Interaction, &mut MatchState, &mut RespondedAt),
         (Changed<Interaction>, With<StimuliButton>),
     >,
 ) {
-    for (interaction, mut match_state) in &mut interaction_query {
-        match *interaction {
-            Interaction::Pressed => {
-                *match_state = MatchState::Match;
-            }
-            _ => {}
+    for (interaction, mut match_state, mut responded_at) in &mut interaction_query {
+        if *interaction == Interaction::Pressed {
+            register_match(&mut match_state, &mut responded_at);
         }
     }
 }
 
-#[derive(Component, PartialEq)]
-pub enum StimuliButtonAction {
-    MatchPosition,
-    MatchAudio,
+fn register_match(match_state: &mut MatchState, responded_at: &mut RespondedAt) {
+    if *match_state != MatchState::Match {
+        *match_state = MatchState::Match;
+        responded_at.0 = Some(Local::now());
+    }
 }
 
+/// The modality a match button responds to.
+#[derive(Component, PartialEq)]
+pub struct StimuliButtonAction(pub ModalityKind);
+
 #[derive(Component, PartialEq)]
 pub enum MatchState {
     Match,
@@ -365,13 +339,17 @@ pub enum MatchState {
     Inactive,
 }
 
+/// When the button's match was registered during the current trial.
+#[derive(Component, Default)]
+pub struct RespondedAt(pub Option<DateTime<Local>>);
+
 #[derive(Component)]
 pub struct StimuliButton;
 
 fn spawn_stimuli_button(
     builder: &mut ChildBuilder,
-    font: Handle<Font>,
     text: &str,
+    text_style: TextStyle,
     action: StimuliButtonAction,
 ) {
     builder
@@ -392,84 +370,104 @@ fn spawn_stimuli_button(
             StimuliButton,
             OnSessionScreen,
             MatchState::Inactive,
+            RespondedAt::default(),
             action,
         ))
         .with_children(|builder| {
-            builder.spawn(TextBundle::from_section(
-                text,
-                TextStyle {
-                    font,
-                    font_size: 40.0,
-                    color: colors::PRIMARY_COLOR.into(),
-                },
-            ));
+            builder.spawn(TextBundle::from_section(text, text_style));
         });
 }
 
-#[derive(Resource)]
-pub struct TrialCount {
-    pub current_count: u32,
-    pub total_count: u32,
-    pub final_trial: bool,
-}
-
-#[derive(Resource)]
-pub struct Score {
-    pub position_correct: u32,
-    pub audio_correct: u32,
-    pub position_false_positive: u32,
-    pub audio_false_positive: u32,
-    pub position_false_negative: u32,
-    pub audio_false_negative: u32,
-}
-
 #[derive(Component)]
 pub struct TrialLabel;
 
-#[derive(Resource)]
-pub struct StimuliGeneration {
-    stimuli: Vec<(TargetLocation, TargetAudio)>,
-    previous: Vec<(TargetLocation, TargetAudio)>,
-    index: usize,
-}
+/// Shows the N of the current trial in variable n-back.
+#[derive(Component)]
+pub struct TrialNLabel;
+
+/// One of the lines a modality adds to the heads-up display, by its index in [`Modality::hud`].
+///
+/// [`Modality::hud`]: crate::Modality::hud
+#[derive(Component)]
+pub struct HudLine(pub ModalityKind, pub usize);
 
 pub fn setup_trial(
     mut commands: Commands,
     settings: Res<SettingValues>,
     stats: Res<StatValues>,
+    registry: Res<ModalityRegistry>,
+    sets: StimulusSets,
     asset_server: Res<AssetServer>,
+    replay: Option<Res<Replay>>,
 ) {
-    let total_count = settings.base_trials
-        + (settings.trial_factor * stats.current_level.pow(settings.trial_exponent));
-
-    commands.insert_resource(TrialCount {
-        current_count: total_count,
-        total_count,
-        final_trial: false,
-    });
-
-    let stimuli = iter::repeat_with(|| (TargetLocation::random(), TargetAudio::random()))
-        .take(stats.current_level as usize)
-        .collect::<Vec<(TargetLocation, TargetAudio)>>();
-
-    commands.insert_resource(StimuliGeneration {
-        stimuli: stimuli,
-        previous: Vec::new(),
-        index: 0,
-    });
-    commands.insert_resource(Score {
-        position_correct: 0,
-        audio_correct: 0,
-        position_false_positive: 0,
-        audio_false_positive: 0,
-        position_false_negative: 0,
-        audio_false_negative: 0,
+    let mode = settings.game_mode;
+    let modalities = settings
+        .modalities()
+        .into_iter()
+        .filter_map(|kind| registry.get(kind))
+        .collect::<Vec<_>>();
+
+    let config = match replay {
+        Some(replay) => replay.0.clone(),
+        None => {
+            let level = stats.level(mode);
+            let generator = match settings.generation_mode {
+                GenerationMode::Probabilistic => Generator::Probabilistic {
+                    chance_of_guaranteed_match: settings.chance_of_guaranteed_match / 100.0,
+                },
+                GenerationMode::ExactCount => Generator::ExactCount {
+                    single: registry
+                        .iter()
+                        .map(|modality| (modality.kind(), modality.targets(&settings)))
+                        .collect(),
+                    all: settings.dual_targets,
+                },
+            };
+
+            EngineConfig {
+                n: level,
+                variant: mode.variant(),
+                total_trials: settings.base_trials
+                    + (settings.trial_factor * level.pow(settings.trial_exponent)),
+                modalities: modalities
+                    .iter()
+                    .map(|modality| (modality.kind(), modality.size(&settings, &sets)))
+                    .collect(),
+                generator,
+                lure_rates: modalities
+                    .iter()
+                    .map(|modality| (modality.kind(), modality.lure_rate(&settings)))
+                    .collect(),
+                seed: rand::random(),
+            }
+        }
+    };
+    let level = config.n;
+    let seed = config.seed;
+    info!("Session seed: {}", seed);
+
+    commands.insert_resource(TrialTimer(Timer::from_seconds(
+        settings.trial_interval_at(level),
+        TimerMode::Repeating,
+    )));
+
+    commands.insert_resource(SessionEngine(NBackEngine::new(config.clone())));
+    commands.insert_resource(TypedAnswer::default());
+    commands.insert_resource(SessionLog {
+        started_at: Local::now(),
+        mode,
+        level,
+        seed,
+        sound_pack: sets.sound_pack.id.clone(),
+        config: Some(config),
+        settings: Some(settings.clone()),
+        trials: Vec::new(),
     });
 
     let text_style = TextStyle {
         font: asset_server.load("fonts/FiraSans-Bold.ttf"),
         font_size: 40.0,
-        color: colors::PRIMARY_COLOR.into(),
+        color: colors::PRIMARY_COLOR,
     };
 
     commands
@@ -493,48 +491,94 @@ pub fn setup_trial(
             ));
 
             parent.spawn((TextBundle::from_section(
-                format!("Level: {}", stats.current_level),
-                text_style,
+                format!("{}: {}", mode.label(), level),
+                text_style.clone(),
+            ),));
+
+            if mode.variant() == NBackVariant::Variable {
+                parent.spawn((
+                    TextBundle::from_section("N: -", text_style.clone()),
+                    TrialNLabel,
+                ));
+            }
+
+            for modality in &modalities {
+                for (line, text) in modality.hud(None, "").into_iter().enumerate() {
+                    parent.spawn((
+                        TextBundle::from_section(text, text_style.clone()),
+                        HudLine(modality.kind(), line),
+                    ));
+                }
+            }
+
+            parent.spawn((TextBundle::from_section(
+                format!("Seed: {}", seed),
+                TextStyle {
+                    font_size: 20.0,
+                    ..text_style
+                },
             ),));
         });
 }
 
 pub fn trial_count_system(
     mut trial_label_query: Query<&mut Text, With<TrialLabel>>,
-    trial_count: ResMut<TrialCount>,
+    engine: Res<SessionEngine>,
 ) {
-    if trial_count.is_changed() {
+    if engine.is_changed() {
         for mut text in &mut trial_label_query {
-            text.sections[0].value = format!("Trials Left: {}", trial_count.current_count);
+            text.sections[0].value = format!("Trials Left: {}", engine.0.trials_remaining());
         }
     }
 }
 
-pub fn setup_targets(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-) {
-    for target_location in TargetLocation::iter() {
-        let target_coordinates = get_target_coordinates(target_location);
+pub fn setup_targets(mut commands: Commands, asset_server: Res<AssetServer>, layout: Res<Layout>) {
+    let scale = layout.cell_size / CELL_SIZE;
+
+    for (cell, coordinates) in layout.cells.iter().enumerate() {
+        let cell = cell as u32;
+        let translation = coordinates.extend(0.0) + Vec3::Y * VERTICAL_OFFSET;
+
+        // Cells are sprites so they can be tinted and swap their image every trial.
         commands.spawn((
-            MaterialMesh2dBundle {
-                mesh: meshes.add(Mesh::from(shape::Quad::default())).into(),
-                transform: Transform::from_translation(Vec3::new(
-                    target_coordinates.0,
-                    target_coordinates.1 + VERTICAL_OFFSET,
-                    0.0,
-                ))
-                .with_scale(Vec3::splat(128.)),
+            SpriteBundle {
+                sprite: Sprite {
+                    color: colors::PRIMARY_COLOR,
+                    custom_size: Some(Vec2::splat(TARGET_SIZE * scale)),
+                    ..Default::default()
+                },
+                transform: Transform::from_translation(translation),
+                visibility: Visibility::Hidden,
+                ..Default::default()
+            },
+            OnSessionScreen,
+            DisplayTargetTime {
+                timer: Timer::default(),
+            },
+            TargetCell(cell),
+        ));
+
+        // Letters are written over the cell and shown only while presented.
+        commands.spawn((
+            Text2dBundle {
+                text: Text::from_section(
+                    "",
+                    TextStyle {
+                        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                        font_size: 96.0 * scale,
+                        color: colors::BACKGROUND_COLOR,
+                    },
+                )
+                .with_alignment(TextAlignment::Center),
+                transform: Transform::from_translation(translation + Vec3::Z),
                 visibility: Visibility::Hidden,
-                material: materials.add(ColorMaterial::from(colors::PRIMARY_COLOR)),
                 ..Default::default()
             },
             OnSessionScreen,
             DisplayTargetTime {
                 timer: Timer::default(),
             },
-            target_location,
+            TargetCell(cell),
         ));
     }
 }
@@ -555,160 +599,252 @@ pub fn stimuli_visibility_system(
     }
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn trial_progression_system(
-    mut target_query: Query<
-        (&TargetLocation, &mut Visibility, &mut DisplayTargetTime),
-        With<TargetLocation>,
+    mut target_query: Query<(
+        &TargetCell,
+        &mut Visibility,
+        &mut DisplayTargetTime,
+        Option<(&mut Sprite, &mut Handle<Image>)>,
+        Option<&mut Text>,
+    )>,
+    mut stimuli_button_query: Query<
+        (&mut MatchState, &mut RespondedAt, &StimuliButtonAction),
+        With<StimuliButton>,
+    >,
+    mut n_label_query: Query<&mut Text, (With<TrialNLabel>, Without<TargetCell>)>,
+    mut hud_query: Query<(&mut Text, &HudLine), (Without<TrialNLabel>, Without<TargetCell>)>,
+    stimulus_query: Query<
+        (Entity, Option<&AudioSink>),
+        Or<(With<StimulusSound>, With<TrialStimulus>)>,
     >,
-    mut stimuli_button_query: Query<(&mut MatchState, &StimuliButtonAction), With<StimuliButton>>,
     mut commands: Commands,
     mut timer: ResMut<TrialTimer>,
-    mut stimuli_generation: ResMut<StimuliGeneration>,
+    mut engine: ResMut<SessionEngine>,
+    mut session_log: ResMut<SessionLog>,
     mut session_state: ResMut<NextState<SessionState>>,
-    mut trial_count: ResMut<TrialCount>,
-    mut score: ResMut<Score>,
+    mut typed_answer: ResMut<TypedAnswer>,
     time: Res<Time>,
     asset_server: Res<AssetServer>,
+    registry: Res<ModalityRegistry>,
+    sets: StimulusSets,
     settings: Res<SettingValues>,
-    stats: Res<StatValues>,
 ) {
-    if timer.0.tick(time.delta()).just_finished() {
-        let trials_completed = trial_count.total_count - trial_count.current_count;
-
-        let generation_index = (trials_completed % stats.current_level) as usize;
-        let mut new_stimuli = Vec::new();
-
-        for (mut match_state, stimuli_button_action) in &mut stimuli_button_query {
-            if trials_completed > stats.current_level {
-                let index = stimuli_generation.index;
-                if let StimuliButtonAction::MatchPosition = *stimuli_button_action {
-                    if stimuli_generation.stimuli[index].0 == stimuli_generation.previous[index].0 {
-                        if MatchState::Match == *match_state {
-                            println!("Position Match");
-                            score.position_correct += 1;
-                        } else {
-                            println!("Position False Negative");
-                            score.position_false_negative += 1;
-                        }
-                    } else {
-                        if MatchState::NonResponse == *match_state {
-                            println!("Position Correct Negative");
-                        } else {
-                            println!(
-                                "{:?} {:?}",
-                                stimuli_generation.stimuli[index].0,
-                                stimuli_generation.previous[index].0
-                            );
-                            score.position_false_positive += 1;
-                            println!("Wrong False Positive");
-                        }
-                    }
-                } else if let StimuliButtonAction::MatchAudio = *stimuli_button_action {
-                    if stimuli_generation.stimuli[index].1 == stimuli_generation.previous[index].1 {
-                        if *match_state == MatchState::Match {
-                            println!("Audio Match");
-                            score.audio_correct += 1;
-                        } else {
-                            println!("Audio False Negative");
-                            score.audio_false_negative += 1;
-                        }
-                    } else {
-                        if *match_state == MatchState::NonResponse {
-                            println!("Audio Correct Negative");
-                        } else {
-                            println!("Audio False Positive");
-                            score.audio_false_positive += 1;
-                        }
-                    }
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    if let Some(record) = session_log.trials.last_mut() {
+        for (match_state, responded_at, stimuli_button_action) in &stimuli_button_query {
+            if *match_state == MatchState::Match {
+                let modality = stimuli_button_action.0;
+                record.response.insert(modality);
+                if let Some(responded_at) = responded_at.0 {
+                    record.responded_at.insert(modality, responded_at);
+                    record
+                        .reaction_ms
+                        .insert(modality, reaction_ms(record.presented_at, responded_at));
                 }
             }
-            if trials_completed >= stats.current_level {
-                *match_state = MatchState::NonResponse;
-            }
         }
 
-        if trial_count.final_trial {
-            session_state.set(SessionState::Exit);
-            return;
+        record.answer = typed_answer.0.parse().ok();
+        if let Some(outcome) = engine.0.resolve(&record.response, record.answer) {
+            debug!(
+                "Trial {}: {:?}, Lures {:?}",
+                outcome.index, outcome.outcomes, outcome.lures
+            );
         }
+    }
+
+    typed_answer.0.clear();
 
-        if generation_index == 0 && trial_count.current_count != trial_count.total_count {
-            let mut rng = rand::thread_rng();
+    let Some(trial) = engine.0.next_trial() else {
+        session_state.set(SessionState::Exit);
+        return;
+    };
 
-            let n_level = stats.current_level as usize;
+    let index = session_log.trials.len() as u32;
+    session_log.trials.push(TrialRecord {
+        index,
+        n: trial.n,
+        stimulus: trial.stimulus.clone(),
+        targets: trial.targets.clone(),
+        lures: trial.lures.clone(),
+        response: Response::new(),
+        presented_at: Local::now(),
+        responded_at: BTreeMap::new(),
+        reaction_ms: BTreeMap::new(),
+        problem: trial.problem,
+        answer: None,
+    });
 
-            for i in 0..n_level {
-                let mut target_location = TargetLocation::random();
-                let mut target_audio = TargetAudio::random();
+    for mut text in &mut n_label_query {
+        text.sections[0].value = match trial.compared_to {
+            Some(_) => format!("N: {}", trial.n),
+            None => "N: -".to_string(),
+        };
+    }
 
-                let location_roll: f32 = rng.gen();
-                if location_roll < (settings.chance_of_guaranteed_match / 100.0) {
-                    target_location = stimuli_generation.stimuli[i].0;
-                }
+    update_hud(&mut hud_query, &registry, Some(&trial), &typed_answer.0);
+
+    if engine.0.accepts_responses() {
+        for (mut match_state, mut responded_at, _) in &mut stimuli_button_query {
+            *match_state = MatchState::NonResponse;
+            responded_at.0 = None;
+        }
+    }
+
+    let mut presentation = Presentation::default();
+    for (kind, index) in &trial.stimulus {
+        if let Some(modality) = registry.get(*kind) {
+            modality.present(*index, &settings, &sets, &mut presentation, &asset_server);
+            modality.spawn(*index, &settings, &sets, &mut commands, &asset_server);
+        }
+    }
 
-                let audio_roll: f32 = rng.gen();
-                println!(
-                    "Setting chance of guaranteed match: {}",
-                    settings.chance_of_guaranteed_match / 100.0
-                );
-                if audio_roll < (settings.chance_of_guaranteed_match / 100.0) {
-                    target_audio = stimuli_generation.stimuli[i].1;
+    // Color and shape are drawn on the lit square, so without a position stream they fill the
+    // middle one, as the letter does.
+    if presentation.cells.is_empty()
+        && (presentation.color.is_some() || presentation.image.is_some())
+    {
+        presentation.cells.push((sets.layout.center(), 0));
+    }
+
+    // Several lit squares are told apart by the color of their stream.
+    let multi_stimulus = presentation.cells.len() > 1;
+    let color = presentation.color.unwrap_or(colors::PRIMARY_COLOR);
+    // The letter is written on the lit square, or in the middle when no square is lit.
+    let letter_location = presentation
+        .cells
+        .first()
+        .map_or(sets.layout.center(), |(cell, _)| *cell);
+    let letter_color = if presentation.cells.is_empty() {
+        colors::PRIMARY_COLOR
+    } else {
+        colors::BACKGROUND_COLOR
+    };
+    for (target_cell, mut target_visibility, mut display_target_time, sprite, text) in
+        &mut target_query
+    {
+        if let Some(mut text) = text {
+            match &presentation.letter {
+                Some(letter) if target_cell.0 == letter_location => {
+                    text.sections[0].value = letter.clone();
+                    text.sections[0].style.color = letter_color;
+                    *target_visibility = Visibility::Visible;
+                    display_target_time.timer =
+                        Timer::from_seconds(settings.display_duration, TimerMode::Once);
                 }
-                new_stimuli.push((target_location, target_audio));
+                _ => *target_visibility = Visibility::Hidden,
+            }
+            continue;
+        }
+
+        let lit_cell = presentation
+            .cells
+            .iter()
+            .find(|(cell, _)| *cell == target_cell.0);
+        if let Some((_, stream)) = lit_cell {
+            *target_visibility = Visibility::Visible;
+            display_target_time.timer =
+                Timer::from_seconds(settings.display_duration, TimerMode::Once);
+            if let Some((mut sprite, mut image)) = sprite {
+                sprite.color = if multi_stimulus {
+                    colors::POSITION_STREAM_COLORS[*stream]
+                } else {
+                    color
+                };
+                *image = presentation.image.clone().unwrap_or_default();
             }
-            commands.insert_resource(StimuliGeneration {
-                stimuli: new_stimuli.clone(),
-                previous: stimuli_generation.stimuli.clone(),
-                index: generation_index,
-            });
         } else {
-            stimuli_generation.index = generation_index;
+            *target_visibility = Visibility::Hidden;
         }
+    }
 
-        let mut current_stimuli = &stimuli_generation.stimuli[generation_index];
-        if generation_index == 0 && trial_count.current_count != trial_count.total_count {
-            current_stimuli = &new_stimuli[0]
+    // Whatever the last trial is still playing or showing is cleared, so sounds never overlap.
+    for (entity, sink) in &stimulus_query {
+        if let Some(sink) = sink {
+            sink.stop();
         }
+        commands.entity(entity).despawn_recursive();
+    }
+    for sound in presentation.sounds {
+        let mut sound_entity = commands.spawn((StimulusSound, OnSessionScreen));
+        match presentation.pan {
+            Some(pan) => sound_entity.insert(PannedSound { source: sound, pan }),
+            None => sound_entity.insert(AudioBundle {
+                source: sound,
+                settings: PlaybackSettings::DESPAWN,
+            }),
+        };
+    }
+    for tone in presentation.tones {
+        commands.spawn((
+            AudioSourceBundle {
+                source: tone,
+                settings: PlaybackSettings::DESPAWN,
+            },
+            StimulusSound,
+            OnSessionScreen,
+        ));
+    }
+}
 
-        for (target_location, mut target_visibility, mut display_target_time) in &mut target_query {
-            if *target_location == current_stimuli.0 {
-                *target_visibility = Visibility::Visible;
-                display_target_time.timer = Timer::from_seconds(0.5, TimerMode::Once);
-            } else {
-                *target_visibility = Visibility::Hidden;
+/// Passes the keys pressed to the modalities answered by typing.
+pub fn answer_input_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    engine: Res<SessionEngine>,
+    registry: Res<ModalityRegistry>,
+    settings: Res<SettingValues>,
+    mut typed_answer: ResMut<TypedAnswer>,
+    mut hud_query: Query<(&mut Text, &HudLine)>,
+) {
+    if engine.0.accepts_responses() {
+        let modalities = settings
+            .modalities()
+            .into_iter()
+            .filter_map(|kind| registry.get(kind))
+            .collect::<Vec<_>>();
+        for key in keyboard_input.get_just_pressed() {
+            for modality in &modalities {
+                modality.type_key(*key, &mut typed_answer.0);
             }
         }
+    }
 
-        play_sound(&mut commands, &asset_server, current_stimuli.1);
+    if typed_answer.is_changed() {
+        update_hud(
+            &mut hud_query,
+            &registry,
+            engine.0.current_trial(),
+            &typed_answer.0,
+        );
+    }
+}
 
-        trial_count.current_count = trial_count.current_count - 1;
-        if trial_count.current_count == 0 {
-            trial_count.final_trial = true;
+fn update_hud<F: ReadOnlyWorldQuery>(
+    hud_query: &mut Query<(&mut Text, &HudLine), F>,
+    registry: &ModalityRegistry,
+    trial: Option<&Trial>,
+    typed: &str,
+) {
+    for (mut text, HudLine(kind, line)) in hud_query {
+        let Some(modality) = registry.get(*kind) else {
+            continue;
+        };
+        if let Some(value) = modality.hud(trial, typed).get(*line) {
+            text.sections[0].value = value.clone();
         }
     }
 }
 
-pub fn play_sound(commands: &mut Commands, asset_server: &Res<AssetServer>, audio: TargetAudio) {
-    println!("Playing sound: {:?}", audio);
-
-    let audio_file = match audio {
-        TargetAudio::C => "letters/c.wav",
-        TargetAudio::H => "letters/h.wav",
-        TargetAudio::K => "letters/k.wav",
-        TargetAudio::L => "letters/l.wav",
-        TargetAudio::Q => "letters/q.wav",
-        TargetAudio::R => "letters/r.wav",
-        TargetAudio::S => "letters/s.wav",
-        TargetAudio::T => "letters/t.wav",
-    };
-    commands.spawn(AudioBundle {
-        source: asset_server.load(audio_file),
-        ..Default::default()
-    });
+fn reaction_ms(onset: DateTime<Local>, responded_at: DateTime<Local>) -> u32 {
+    (responded_at - onset).num_milliseconds().max(0) as u32
 }
 
 pub fn target_transition_system(
-    mut target_query: Query<(&mut Visibility, &mut DisplayTargetTime), With<TargetLocation>>,
+    mut target_query: Query<(&mut Visibility, &mut DisplayTargetTime), With<TargetCell>>,
     time: Res<Time>,
 ) {
     for (mut visibility, mut display_target_time) in &mut target_query {
@@ -718,8 +854,24 @@ pub fn target_transition_system(
     }
 }
 
-pub fn setup_stimuli_buttons(mut commands: Commands, asset_server: Res<AssetServer>) {
+pub fn setup_stimuli_buttons(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    registry: Res<ModalityRegistry>,
+    settings: Res<SettingValues>,
+) {
+    let modalities = response_categories(&settings.modalities())
+        .into_iter()
+        .filter_map(|kind| registry.get(kind))
+        .filter(|modality| modality.key().is_some())
+        .collect::<Vec<_>>();
+    let kinds = modalities
+        .iter()
+        .map(|modality| modality.kind())
+        .collect::<Vec<_>>();
     let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    // Long rows of buttons get smaller labels so they still fit the window.
+    let font_size = if modalities.len() > 3 { 24.0 } else { 40.0 };
 
     commands
         .spawn((
@@ -740,29 +892,37 @@ pub fn setup_stimuli_buttons(mut commands: Commands, asset_server: Res<AssetServ
                 .spawn(NodeBundle {
                     style: Style {
                         flex_direction: FlexDirection::Row,
-                        column_gap: Val::Px(2.0 * CELL_SIZE),
+                        // Two buttons sit either side of the grid, more are packed together
+                        // so they still fit the window.
+                        column_gap: Val::Px(if modalities.len() > 2 {
+                            0.0
+                        } else {
+                            2.0 * CELL_SIZE
+                        }),
                         position_type: PositionType::Absolute,
                         ..Default::default()
                     },
                     ..Default::default()
                 })
                 .with_children(|builder| {
-                    spawn_stimuli_button(
-                        builder,
-                        font.clone(),
-                        "A: Position",
-                        StimuliButtonAction::MatchPosition,
-                    );
-                    spawn_stimuli_button(
-                        builder,
-                        font.clone(),
-                        "L: Audio",
-                        StimuliButtonAction::MatchAudio,
-                    );
+                    for modality in &modalities {
+                        if let Some(key) = modality.key() {
+                            spawn_stimuli_button(
+                                builder,
+                                &format!("{:?}: {}", key, modality.label()),
+                                TextStyle {
+                                    font: font.clone(),
+                                    font_size,
+                                    color: modality.button_color(&kinds),
+                                },
+                                StimuliButtonAction(modality.kind()),
+                            );
+                        }
+                    }
                 });
         });
 }
 
-fn round_float(num: f32) -> f32 {
+pub fn round_float(num: f32) -> f32 {
     (num * 100.0).round() / 100.0
 }
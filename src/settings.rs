@@ -1,8 +1,14 @@
-use crate::{despawn_screen, setup_database, AppState, Mode, SettingValues, StatValues};
+use crate::{
+    despawn_screen, AppState, GameMode, GenerationMode, LayoutKind, LevelMetric, LevelPolicyKind,
+    ModalityKind, ModalityRegistry, Mode, SessionLog, SettingValues, SoundPack, StatValues,
+    ToneKind, DEFAULT_SOUND_PACK,
+};
 use bevy::prelude::*;
 use bevy_egui::{egui, EguiContexts, EguiPlugin};
 use bevy_pkv::PkvStore;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use strum::IntoEnumIterator;
 
 pub struct SettingsPlugin;
 
@@ -27,9 +33,36 @@ pub struct StagedSettingValues {
     pub trial_factor: u32,
     pub trial_exponent: u32,
     pub mode: Mode,
+    pub game_mode: GameMode,
+    pub custom_modalities: BTreeSet<ModalityKind>,
+    pub stimulus_count: u32,
+    pub layout: LayoutKind,
+    pub grid_columns: u32,
+    pub grid_rows: u32,
+    pub custom_layout: String,
+    pub sound_pack: String,
+    pub tone_kind: ToneKind,
+    pub tone_count: u32,
+    pub tone_base_frequency: f32,
+    pub pan_positions: u32,
+    pub trial_interval: f32,
+    pub display_duration: f32,
+    pub speed_ramp: f32,
+    pub level_policy: LevelPolicyKind,
     pub raise_threshold: f32,
     pub lower_threshold: f32,
+    pub level_metric: LevelMetric,
+    pub raise_d_prime: f32,
+    pub lower_d_prime: f32,
+    pub staircase_criterion: f32,
+    pub staircase_up_step: f32,
+    pub staircase_down_step: f32,
     pub chance_of_guaranteed_match: f32,
+    pub generation_mode: GenerationMode,
+    pub modality_targets: BTreeMap<ModalityKind, u32>,
+    pub dual_targets: u32,
+    pub lure_rates: BTreeMap<ModalityKind, f32>,
+    pub replay_session: Option<u32>,
 }
 
 impl Default for StagedSettingValues {
@@ -39,9 +72,36 @@ impl Default for StagedSettingValues {
             trial_factor: 2,
             trial_exponent: 2,
             mode: Mode::Auto,
+            game_mode: GameMode::Dual,
+            custom_modalities: BTreeSet::from([ModalityKind::POSITION, ModalityKind::AUDIO]),
+            stimulus_count: 2,
+            layout: LayoutKind::Grid,
+            grid_columns: 3,
+            grid_rows: 3,
+            custom_layout: "assets/layouts/custom.txt".to_string(),
+            sound_pack: DEFAULT_SOUND_PACK.to_string(),
+            tone_kind: ToneKind::Pitch,
+            tone_count: 6,
+            tone_base_frequency: 261.63,
+            pan_positions: 5,
+            trial_interval: 3.0,
+            display_duration: 0.5,
+            speed_ramp: 0.0,
+            level_policy: LevelPolicyKind::Threshold,
             raise_threshold: 0.8,
             lower_threshold: 0.2,
+            level_metric: LevelMetric::PercentCorrect,
+            raise_d_prime: 2.5,
+            lower_d_prime: 1.0,
+            staircase_criterion: 0.8,
+            staircase_up_step: 0.25,
+            staircase_down_step: 0.75,
             chance_of_guaranteed_match: 0.5,
+            generation_mode: GenerationMode::Probabilistic,
+            modality_targets: BTreeMap::new(),
+            dual_targets: 2,
+            lure_rates: BTreeMap::new(),
+            replay_session: None,
         }
     }
 }
@@ -55,19 +115,54 @@ pub fn setup_settings(mut commands: Commands, settings: Res<SettingValues>) {
         trial_factor: settings.trial_factor,
         trial_exponent: settings.trial_exponent,
         mode: settings.mode,
+        game_mode: settings.game_mode,
+        custom_modalities: settings.custom_modalities.clone(),
+        stimulus_count: settings.stimulus_count,
+        layout: settings.layout,
+        grid_columns: settings.grid_columns,
+        grid_rows: settings.grid_rows,
+        custom_layout: settings.custom_layout.clone(),
+        sound_pack: settings.sound_pack.clone(),
+        tone_kind: settings.tone_kind,
+        tone_count: settings.tone_count,
+        tone_base_frequency: settings.tone_base_frequency,
+        pan_positions: settings.pan_positions,
+        trial_interval: settings.trial_interval,
+        display_duration: settings.display_duration,
+        speed_ramp: settings.speed_ramp,
+        level_policy: settings.level_policy,
         raise_threshold: settings.raise_threshold,
         lower_threshold: settings.lower_threshold,
+        level_metric: settings.level_metric,
+        raise_d_prime: settings.raise_d_prime,
+        lower_d_prime: settings.lower_d_prime,
+        staircase_criterion: settings.staircase_criterion,
+        staircase_up_step: settings.staircase_up_step,
+        staircase_down_step: settings.staircase_down_step,
         chance_of_guaranteed_match: settings.chance_of_guaranteed_match,
+        generation_mode: settings.generation_mode,
+        modality_targets: settings.modality_targets.clone(),
+        dual_targets: settings.dual_targets,
+        lure_rates: settings.lure_rates.clone(),
+        replay_session: settings.replay_session,
     });
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn settings_systems(
     mut commands: Commands,
     mut contexts: EguiContexts,
     mut staged_settings: ResMut<StagedSettingValues>,
     mut pkv: ResMut<PkvStore>,
     mut stats: ResMut<StatValues>,
+    registry: Res<ModalityRegistry>,
+    mut sound_packs: Local<Vec<String>>,
+    mut replay_summary: Local<Option<(u32, String)>>,
 ) {
+    if sound_packs.is_empty() {
+        *sound_packs = SoundPack::available();
+    }
+
     let ctx = contexts.ctx_mut();
     let screen_size = ctx.available_rect();
     let position = egui::Pos2 {
@@ -85,6 +180,46 @@ pub fn settings_systems(
             ui.set_height(screen_size.height() / 2.0);
             ui.separator();
 
+            let game_mode = &mut staged_settings.game_mode;
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Game Mode:");
+                for option in GameMode::iter() {
+                    ui.selectable_value(game_mode, option, option.label());
+                }
+            });
+
+            if staged_settings.game_mode == GameMode::MultiStimulus {
+                let stimulus_count = &mut staged_settings.stimulus_count;
+                ui.add(egui::Slider::new(stimulus_count, 2..=4).text("Squares per Trial"));
+            }
+
+            if staged_settings.game_mode == GameMode::Custom {
+                let custom_modalities = &mut staged_settings.custom_modalities;
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("Modalities:");
+                    // The extra position streams are told apart by color, so they only come
+                    // with multi-stimulus mode. Cross-modal comparisons join in on their own
+                    // once both letter modalities are ticked.
+                    for modality in registry.iter().filter(|modality| {
+                        let kind = modality.kind();
+                        kind.position_stream().unwrap_or(0) == 0 && !kind.is_cross_modal()
+                    }) {
+                        let kind = modality.kind();
+                        let enabled = modality.available_with(custom_modalities);
+                        let mut checked = custom_modalities.contains(&kind);
+                        ui.add_enabled(
+                            enabled,
+                            egui::Checkbox::new(&mut checked, modality.label()),
+                        );
+                        if checked {
+                            custom_modalities.insert(kind);
+                        } else if custom_modalities.len() > 1 {
+                            custom_modalities.remove(&kind);
+                        }
+                    }
+                });
+            }
+
             let selected_mode = &mut staged_settings.mode;
             ui.horizontal(|ui| {
                 ui.label("Mode:");
@@ -93,7 +228,7 @@ pub fn settings_systems(
             });
 
             if *selected_mode == Mode::Manual {
-                let manual_level = &mut stats.current_level;
+                let manual_level = stats.level_mut(staged_settings.game_mode);
                 ui.add(egui::Slider::new(manual_level, 1..=50).text("Manual Level"));
             }
 
@@ -110,17 +245,208 @@ pub fn settings_systems(
 
             ui.separator();
 
-            let raise_threshold = &mut staged_settings.raise_threshold;
-            ui.add(egui::Slider::new(raise_threshold, 0.5..=1.0).text("Raise Threshold"));
+            let trial_interval = &mut staged_settings.trial_interval;
+            ui.add(egui::Slider::new(trial_interval, 0.5..=5.0).text("Trial Interval (s)"));
 
-            let lower_threshold = &mut staged_settings.lower_threshold;
-            ui.add(egui::Slider::new(lower_threshold, 0.0..=0.49).text("Lower Threshold"));
+            let display_duration = &mut staged_settings.display_duration;
+            ui.add(egui::Slider::new(display_duration, 0.1..=2.0).text("Display Duration (s)"));
 
-            let chance_of_guaranteed_match = &mut staged_settings.chance_of_guaranteed_match;
-            ui.add(
-                egui::Slider::new(chance_of_guaranteed_match, 0.0..=1.0)
-                    .text("Chance of Guaranteed Match"),
-            );
+            let speed_ramp = &mut staged_settings.speed_ramp;
+            ui.add(egui::Slider::new(speed_ramp, 0.0..=0.5).text("Speed Ramp (s per level)"));
+
+            ui.separator();
+
+            let level_policy = &mut staged_settings.level_policy;
+            ui.horizontal(|ui| {
+                ui.label("Level Policy:");
+                ui.selectable_value(level_policy, LevelPolicyKind::Threshold, "Threshold");
+                ui.selectable_value(level_policy, LevelPolicyKind::Jaeggi, "Jaeggi");
+                ui.selectable_value(
+                    level_policy,
+                    LevelPolicyKind::BrainWorkshop,
+                    "Brain Workshop",
+                );
+                ui.selectable_value(level_policy, LevelPolicyKind::Staircase, "Staircase");
+            });
+
+            match staged_settings.level_policy {
+                LevelPolicyKind::Threshold => {
+                    let level_metric = &mut staged_settings.level_metric;
+                    ui.horizontal(|ui| {
+                        ui.label("Level Metric:");
+                        ui.selectable_value(
+                            level_metric,
+                            LevelMetric::PercentCorrect,
+                            "Percent Correct",
+                        );
+                        ui.selectable_value(level_metric, LevelMetric::DPrime, "d′");
+                    });
+
+                    if staged_settings.level_metric == LevelMetric::PercentCorrect {
+                        let raise_threshold = &mut staged_settings.raise_threshold;
+                        ui.add(
+                            egui::Slider::new(raise_threshold, 0.5..=1.0).text("Raise Threshold"),
+                        );
+
+                        let lower_threshold = &mut staged_settings.lower_threshold;
+                        ui.add(
+                            egui::Slider::new(lower_threshold, 0.0..=0.49).text("Lower Threshold"),
+                        );
+                    } else {
+                        let raise_d_prime = &mut staged_settings.raise_d_prime;
+                        ui.add(egui::Slider::new(raise_d_prime, 0.0..=5.0).text("Raise d′"));
+
+                        let lower_d_prime = &mut staged_settings.lower_d_prime;
+                        ui.add(egui::Slider::new(lower_d_prime, -1.0..=5.0).text("Lower d′"));
+                    }
+                }
+                LevelPolicyKind::Staircase => {
+                    let staircase_criterion = &mut staged_settings.staircase_criterion;
+                    ui.add(
+                        egui::Slider::new(staircase_criterion, 0.0..=1.0).text("Success Threshold"),
+                    );
+
+                    let staircase_up_step = &mut staged_settings.staircase_up_step;
+                    ui.add(egui::Slider::new(staircase_up_step, 0.05..=1.0).text("Up Step"));
+
+                    let staircase_down_step = &mut staged_settings.staircase_down_step;
+                    ui.add(egui::Slider::new(staircase_down_step, 0.05..=1.0).text("Down Step"));
+                }
+                LevelPolicyKind::Jaeggi | LevelPolicyKind::BrainWorkshop => {}
+            }
+
+            ui.separator();
+
+            let generation_mode = &mut staged_settings.generation_mode;
+            ui.horizontal(|ui| {
+                ui.label("Generation:");
+                ui.selectable_value(
+                    generation_mode,
+                    GenerationMode::Probabilistic,
+                    "Probabilistic",
+                );
+                ui.selectable_value(generation_mode, GenerationMode::ExactCount, "Exact Count");
+            });
+
+            if staged_settings.generation_mode == GenerationMode::Probabilistic {
+                let chance_of_guaranteed_match = &mut staged_settings.chance_of_guaranteed_match;
+                ui.add(
+                    egui::Slider::new(chance_of_guaranteed_match, 0.0..=1.0)
+                        .text("Chance of Guaranteed Match"),
+                );
+            } else {
+                // Modalities answered without a match button have no targets to plan.
+                for modality in registry.iter().filter(|modality| modality.key().is_some()) {
+                    let targets = staged_settings
+                        .modality_targets
+                        .entry(modality.kind())
+                        .or_insert_with(|| modality.default_targets());
+                    ui.add(
+                        egui::Slider::new(targets, 0..=20)
+                            .text(format!("{} Targets", modality.label())),
+                    );
+                }
+
+                let dual_targets = &mut staged_settings.dual_targets;
+                ui.add(egui::Slider::new(dual_targets, 0..=20).text("All-Modality Targets"));
+            }
+
+            let layout = &mut staged_settings.layout;
+            ui.horizontal(|ui| {
+                ui.label("Layout:");
+                ui.selectable_value(layout, LayoutKind::Grid, "Grid");
+                ui.selectable_value(layout, LayoutKind::Ring, "Ring");
+                ui.selectable_value(layout, LayoutKind::Hexagon, "Hexagon");
+                ui.selectable_value(layout, LayoutKind::Scatter, "Scatter");
+                ui.selectable_value(layout, LayoutKind::Custom, "Custom");
+            });
+
+            match staged_settings.layout {
+                LayoutKind::Grid => {
+                    let grid_columns = &mut staged_settings.grid_columns;
+                    ui.add(egui::Slider::new(grid_columns, 3..=6).text("Grid Columns"));
+
+                    let grid_rows = &mut staged_settings.grid_rows;
+                    ui.add(egui::Slider::new(grid_rows, 3..=6).text("Grid Rows"));
+                }
+                LayoutKind::Custom => {
+                    ui.horizontal(|ui| {
+                        ui.label("Layout File:");
+                        ui.text_edit_singleline(&mut staged_settings.custom_layout);
+                    });
+                }
+                _ => {}
+            }
+
+            if staged_settings.game_mode == GameMode::Custom {
+                let picked = registry
+                    .iter()
+                    .filter(|modality| staged_settings.custom_modalities.contains(&modality.kind()))
+                    .collect::<Vec<_>>();
+                for modality in picked {
+                    modality.settings_ui(ui, &mut staged_settings);
+                }
+            }
+
+            let sound_pack = &mut staged_settings.sound_pack;
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Sound Pack:");
+                for id in sound_packs.iter() {
+                    ui.selectable_value(sound_pack, id.clone(), id);
+                }
+            });
+
+            // Cross-modal comparisons have no stimuli of their own to repeat.
+            ui.collapsing("Lure Rates", |ui| {
+                for modality in registry.iter().filter(|modality| {
+                    modality.key().is_some() && !modality.kind().is_cross_modal()
+                }) {
+                    let lure_rate = staged_settings
+                        .lure_rates
+                        .entry(modality.kind())
+                        .or_insert(0.0);
+                    ui.add(
+                        egui::Slider::new(lure_rate, 0.0..=1.0)
+                            .text(format!("{} Lure Rate", modality.label())),
+                    );
+                }
+            });
+
+            ui.separator();
+
+            let replay_session = &mut staged_settings.replay_session;
+            ui.horizontal(|ui| {
+                let mut replay = replay_session.is_some();
+                ui.add_enabled(
+                    stats.total_sessions > 0,
+                    egui::Checkbox::new(&mut replay, "Replay Session"),
+                );
+                if !replay {
+                    *replay_session = None;
+                    return;
+                }
+
+                let id = replay_session.get_or_insert(stats.total_sessions);
+                ui.add(egui::DragValue::new(id).clamp_range(1..=stats.total_sessions));
+
+                // The log is only read again when another session is picked.
+                if replay_summary.as_ref().map(|(summary_id, _)| summary_id) != Some(id) {
+                    let summary = match pkv.get::<SessionLog>(&SessionLog::key(*id)) {
+                        Ok(log) if log.config.is_some() && log.settings.is_some() => format!(
+                            "{} {}, {}",
+                            log.mode.label(),
+                            log.level,
+                            log.started_at.format("%Y-%m-%d %H:%M")
+                        ),
+                        Ok(_) => "Saved by an older version, can't be replayed".to_string(),
+                        Err(_) => "Not found".to_string(),
+                    };
+                    *replay_summary = Some((*id, summary));
+                }
+                if let Some((_, summary)) = replay_summary.as_ref() {
+                    ui.label(summary);
+                }
+            });
 
             ui.separator();
 
@@ -130,9 +456,36 @@ pub fn settings_systems(
                     trial_factor: staged_settings.trial_factor,
                     trial_exponent: staged_settings.trial_exponent,
                     mode: staged_settings.mode,
+                    game_mode: staged_settings.game_mode,
+                    custom_modalities: staged_settings.custom_modalities.clone(),
+                    stimulus_count: staged_settings.stimulus_count,
+                    layout: staged_settings.layout,
+                    grid_columns: staged_settings.grid_columns,
+                    grid_rows: staged_settings.grid_rows,
+                    custom_layout: staged_settings.custom_layout.clone(),
+                    sound_pack: staged_settings.sound_pack.clone(),
+                    tone_kind: staged_settings.tone_kind,
+                    tone_count: staged_settings.tone_count,
+                    tone_base_frequency: staged_settings.tone_base_frequency,
+                    pan_positions: staged_settings.pan_positions,
+                    trial_interval: staged_settings.trial_interval,
+                    display_duration: staged_settings.display_duration,
+                    speed_ramp: staged_settings.speed_ramp,
+                    level_policy: staged_settings.level_policy,
                     raise_threshold: staged_settings.raise_threshold,
                     lower_threshold: staged_settings.lower_threshold,
+                    level_metric: staged_settings.level_metric,
+                    raise_d_prime: staged_settings.raise_d_prime,
+                    lower_d_prime: staged_settings.lower_d_prime,
+                    staircase_criterion: staged_settings.staircase_criterion,
+                    staircase_up_step: staged_settings.staircase_up_step,
+                    staircase_down_step: staged_settings.staircase_down_step,
                     chance_of_guaranteed_match: staged_settings.chance_of_guaranteed_match,
+                    generation_mode: staged_settings.generation_mode,
+                    modality_targets: staged_settings.modality_targets.clone(),
+                    dual_targets: staged_settings.dual_targets,
+                    lure_rates: staged_settings.lure_rates.clone(),
+                    replay_session: staged_settings.replay_session,
                 };
                 pkv.set("settingValues", &setting_values)
                     .expect("failed to store settings");
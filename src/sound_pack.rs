@@ -0,0 +1,211 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Folder under `assets` holding one folder per sound pack.
+pub const SOUND_PACK_DIR: &str = "sounds";
+pub const DEFAULT_SOUND_PACK: &str = "letters";
+/// Pack of the spoken digits of arithmetic n-back.
+pub const DIGIT_SOUND_PACK: &str = "digits";
+
+/// The spoken stimuli of the session, read from `assets/sounds/<pack>/manifest.ron`. Audio
+/// stimuli are indexes into `stimuli`, so the pack size is the audio set size.
+#[derive(Resource, Clone, Debug)]
+pub struct SoundPack {
+    /// Folder of the pack under `assets/sounds`.
+    pub id: String,
+    pub name: String,
+    pub stimuli: Vec<SoundStimulus>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SoundStimulus {
+    pub id: String,
+    /// Shown wherever the stimulus is written out, e.g. as a visual letter.
+    pub label: String,
+    /// Asset path of the recording, resolved from the pack folder when the pack is loaded.
+    pub file: String,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    name: String,
+    stimuli: Vec<SoundStimulus>,
+}
+
+impl SoundPack {
+    /// Loads and validates the pack in folder `id`: the manifest must parse, list at least two
+    /// stimuli with distinct IDs and non-empty labels, and every recording must exist.
+    pub fn load(id: &str) -> Result<Self, String> {
+        Self::read(&Path::new("assets").join(SOUND_PACK_DIR).join(id), id)
+    }
+
+    /// Loads the pack in `directory` as pack `id`, which sets where its recordings are loaded
+    /// from.
+    fn read(directory: &Path, id: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(directory.join("manifest.ron"))
+            .map_err(|error| format!("manifest.ron: {}", error))?;
+        let manifest: Manifest =
+            ron::from_str(&contents).map_err(|error| format!("manifest.ron: {}", error))?;
+
+        if manifest.stimuli.len() < 2 {
+            return Err("a sound pack needs at least 2 stimuli".to_string());
+        }
+        let mut ids = BTreeSet::new();
+        for stimulus in &manifest.stimuli {
+            if !ids.insert(&stimulus.id) {
+                return Err(format!("stimulus {} is listed twice", stimulus.id));
+            }
+            if stimulus.label.is_empty() {
+                return Err(format!("stimulus {} has no label", stimulus.id));
+            }
+            if !directory.join(&stimulus.file).is_file() {
+                return Err(format!("{} is missing", stimulus.file));
+            }
+        }
+
+        Ok(SoundPack {
+            id: id.to_string(),
+            name: manifest.name,
+            stimuli: manifest
+                .stimuli
+                .into_iter()
+                .map(|stimulus| SoundStimulus {
+                    file: format!("{}/{}/{}", SOUND_PACK_DIR, id, stimulus.file),
+                    ..stimulus
+                })
+                .collect(),
+        })
+    }
+
+    /// Folders under `assets/sounds` that have a manifest, in name order.
+    pub fn available() -> Vec<String> {
+        let Ok(entries) = std::fs::read_dir(Path::new("assets").join(SOUND_PACK_DIR)) else {
+            return Vec::new();
+        };
+        let mut ids = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().join("manifest.ron").is_file())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    pub fn size(&self) -> u32 {
+        self.stimuli.len() as u32
+    }
+}
+
+/// The spoken digits of arithmetic n-back. Arithmetic stimuli are the digits themselves, so the
+/// pack must list them from 0 up, in order.
+#[derive(Resource, Clone, Debug)]
+pub struct DigitPack(pub SoundPack);
+
+impl DigitPack {
+    pub fn load() -> Result<Self, String> {
+        let pack = SoundPack::load(DIGIT_SOUND_PACK)?;
+        for (digit, stimulus) in pack.stimuli.iter().enumerate() {
+            if stimulus.id != digit.to_string() {
+                return Err(format!(
+                    "stimulus {} is listed where digit {} should be",
+                    stimulus.id, digit
+                ));
+            }
+        }
+        Ok(DigitPack(pack))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STIMULI: &str = r#"[
+        (id: "a", label: "A", file: "a.wav"),
+        (id: "b", label: "B", file: "b.wav"),
+    ]"#;
+
+    /// Writes a pack with `stimuli` and recordings `a.wav` and `b.wav` to a folder of its own,
+    /// and reads it back.
+    fn read_pack(name: &str, stimuli: &str) -> Result<SoundPack, String> {
+        let directory = std::env::temp_dir().join(format!("dual-n-back-pack-{name}"));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("manifest.ron"),
+            format!("(name: \"Test\", stimuli: {stimuli})"),
+        )
+        .unwrap();
+        for file in ["a.wav", "b.wav"] {
+            std::fs::write(directory.join(file), []).unwrap();
+        }
+        let pack = SoundPack::read(&directory, name);
+        std::fs::remove_dir_all(&directory).unwrap();
+        pack
+    }
+
+    #[test]
+    fn read_resolves_recordings_from_the_pack_folder() {
+        let pack = read_pack("good", STIMULI).unwrap();
+        assert_eq!(pack.name, "Test");
+        assert_eq!(pack.size(), 2);
+        assert_eq!(pack.stimuli[1].file, "sounds/good/b.wav");
+    }
+
+    #[test]
+    fn bundled_packs_load() {
+        assert!(SoundPack::load(DEFAULT_SOUND_PACK).is_ok());
+        assert_eq!(DigitPack::load().unwrap().0.size(), 10);
+    }
+
+    #[test]
+    fn read_rejects_a_manifest_that_does_not_parse() {
+        let error = read_pack("unparsed", "[(id: \"a\")]").unwrap_err();
+        assert!(error.starts_with("manifest.ron: "), "{error}");
+    }
+
+    #[test]
+    fn read_rejects_fewer_than_two_stimuli() {
+        assert_eq!(
+            read_pack("single", r#"[(id: "a", label: "A", file: "a.wav")]"#).unwrap_err(),
+            "a sound pack needs at least 2 stimuli"
+        );
+    }
+
+    #[test]
+    fn read_rejects_duplicate_ids() {
+        let stimuli = r#"[
+            (id: "a", label: "A", file: "a.wav"),
+            (id: "a", label: "B", file: "b.wav"),
+        ]"#;
+        assert_eq!(
+            read_pack("duplicate", stimuli).unwrap_err(),
+            "stimulus a is listed twice"
+        );
+    }
+
+    #[test]
+    fn read_rejects_empty_labels() {
+        let stimuli = r#"[
+            (id: "a", label: "A", file: "a.wav"),
+            (id: "b", label: "", file: "b.wav"),
+        ]"#;
+        assert_eq!(
+            read_pack("unlabeled", stimuli).unwrap_err(),
+            "stimulus b has no label"
+        );
+    }
+
+    #[test]
+    fn read_rejects_missing_recordings() {
+        let stimuli = r#"[
+            (id: "a", label: "A", file: "a.wav"),
+            (id: "c", label: "C", file: "c.wav"),
+        ]"#;
+        assert_eq!(
+            read_pack("missing", stimuli).unwrap_err(),
+            "c.wav is missing"
+        );
+    }
+}
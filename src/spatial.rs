@@ -0,0 +1,109 @@
+use bevy::{
+    audio::{CpalSample, Source},
+    prelude::*,
+    reflect::{TypePath, TypeUuid},
+};
+use std::f32::consts::FRAC_PI_4;
+use std::time::Duration;
+
+/// A recording played from a point between the left and right speakers.
+#[derive(TypeUuid, TypePath, Clone)]
+#[uuid = "0b8e5f0f-6a5c-4d51-9a0e-3c2f7d4e8b61"]
+pub struct PannedAudio {
+    pub source: AudioSource,
+    /// From -1 for far left to 1 for far right.
+    pub pan: f32,
+}
+
+impl Decodable for PannedAudio {
+    type DecoderItem = f32;
+    type Decoder = PannedDecoder;
+
+    fn decoder(&self) -> Self::Decoder {
+        // Constant power panning, so the sound is equally loud wherever it comes from.
+        let angle = (self.pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+        let input = self.source.decoder();
+        PannedDecoder {
+            input_channels: input.channels().max(1),
+            input,
+            gains: [angle.cos(), angle.sin()],
+            right: None,
+        }
+    }
+}
+
+/// Stereo samples of a [`PannedAudio`]: each input frame is mixed down to mono and split
+/// between the two channels.
+pub struct PannedDecoder {
+    input: <AudioSource as Decodable>::Decoder,
+    input_channels: u16,
+    gains: [f32; 2],
+    /// The right half of the frame whose left half was returned last.
+    right: Option<f32>,
+}
+
+impl Iterator for PannedDecoder {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(right) = self.right.take() {
+            return Some(right);
+        }
+
+        let mut frame = 0.0;
+        for _ in 0..self.input_channels {
+            frame += self.input.next()?.to_sample::<f32>();
+        }
+        let mono = frame / self.input_channels as f32;
+        self.right = Some(mono * self.gains[1]);
+        Some(mono * self.gains[0])
+    }
+}
+
+impl Source for PannedDecoder {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+/// A sound waiting for its recording to load before it can be panned and played.
+#[derive(Component)]
+pub struct PannedSound {
+    pub source: Handle<AudioSource>,
+    pub pan: f32,
+}
+
+/// Starts the panned sounds whose recordings have loaded.
+pub fn panned_sound_system(
+    mut commands: Commands,
+    sound_query: Query<(Entity, &PannedSound)>,
+    audio_sources: Res<Assets<AudioSource>>,
+    mut panned_audio: ResMut<Assets<PannedAudio>>,
+) {
+    for (entity, sound) in &sound_query {
+        if let Some(source) = audio_sources.get(&sound.source) {
+            commands
+                .entity(entity)
+                .remove::<PannedSound>()
+                .insert(AudioSourceBundle {
+                    source: panned_audio.add(PannedAudio {
+                        source: source.clone(),
+                        pan: sound.pan,
+                    }),
+                    settings: PlaybackSettings::DESPAWN,
+                });
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{SettingValues, ToneKind};
+use bevy::{
+    audio::Source,
+    prelude::*,
+    reflect::{TypePath, TypeUuid},
+};
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+const SAMPLE_RATE: u32 = 44_100;
+const TONE_SECONDS: f32 = 0.5;
+/// Fade in and out, so the tone starts and stops without a click.
+const ATTACK_SECONDS: f32 = 0.01;
+const RELEASE_SECONDS: f32 = 0.08;
+const VOLUME: f32 = 0.3;
+/// Semitones of the major scale above its root.
+const MAJOR_SCALE: [u32; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// A tone or chord synthesized in-process, played like any other audio source.
+#[derive(TypeUuid, TypePath, Clone, Debug)]
+#[uuid = "6e7d39a2-5040-45e1-95fe-d0c740f3b9bb"]
+pub struct Tone {
+    /// Pitches sounded together, in hertz.
+    pub frequencies: Vec<f32>,
+}
+
+impl Decodable for Tone {
+    type DecoderItem = f32;
+    type Decoder = ToneDecoder;
+
+    fn decoder(&self) -> Self::Decoder {
+        ToneDecoder {
+            frequencies: self.frequencies.clone(),
+            sample: 0,
+            samples: (TONE_SECONDS * SAMPLE_RATE as f32) as u32,
+        }
+    }
+}
+
+/// Mono samples of a [`Tone`]: the average of its sine waves, faded in and out.
+pub struct ToneDecoder {
+    frequencies: Vec<f32>,
+    sample: u32,
+    samples: u32,
+}
+
+impl Iterator for ToneDecoder {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.sample >= self.samples {
+            return None;
+        }
+
+        let time = self.sample as f32 / SAMPLE_RATE as f32;
+        let remaining = (self.samples - self.sample) as f32 / SAMPLE_RATE as f32;
+        let envelope = (time / ATTACK_SECONDS)
+            .min(remaining / RELEASE_SECONDS)
+            .min(1.0);
+        let wave = self
+            .frequencies
+            .iter()
+            .map(|frequency| (TAU * frequency * time).sin())
+            .sum::<f32>()
+            / self.frequencies.len().max(1) as f32;
+
+        self.sample += 1;
+        Some(VOLUME * envelope * wave)
+    }
+}
+
+impl Source for ToneDecoder {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f32(TONE_SECONDS))
+    }
+}
+
+/// The tones of the session, one per stimulus of the tone modality.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct ToneSet(pub Vec<Handle<Tone>>);
+
+impl ToneSet {
+    /// Climbs the major scale from the base frequency: single notes for pitches, or the triad
+    /// built on each note for chords.
+    pub fn from_settings(settings: &SettingValues, tones: &mut Assets<Tone>) -> Self {
+        let note = |degree: u32| {
+            let semitones = 12 * (degree / 7) + MAJOR_SCALE[(degree % 7) as usize];
+            settings.tone_base_frequency * 2f32.powf(semitones as f32 / 12.0)
+        };
+        ToneSet(
+            (0..settings.tone_count)
+                .map(|degree| {
+                    let frequencies = match settings.tone_kind {
+                        ToneKind::Pitch => vec![note(degree)],
+                        ToneKind::Chord => vec![note(degree), note(degree + 2), note(degree + 4)],
+                    };
+                    tones.add(Tone { frequencies })
+                })
+                .collect(),
+        )
+    }
+
+    pub fn size(&self) -> u32 {
+        self.0.len() as u32
+    }
+}